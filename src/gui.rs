@@ -0,0 +1,333 @@
+//! The gui, served as a page to the browser so it only needs the standard
+//! library. The page posts its form to `/generate`, which answers with the
+//! output as a PNG or with the error as text, and saves the PNG through the
+//! browser's own save dialog, so the server never writes any files.
+//!
+//! Only this machine can connect, and requests whose `Host` or `Origin` isn't
+//! the gui itself are refused, so other pages open in the browser can't use it.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use clap::ArgEnum;
+use image::{DynamicImage, ImageOutputFormat};
+use tile_collapse::{
+    attempt_seed,
+    model::{Heuristic, Model, SimpleTiled},
+};
+
+use crate::read_config;
+
+/// The port the gui is served on unless `--port` says otherwise.
+pub const DEFAULT_PORT: u16 = 7878;
+
+/// How many seeds Generate tries before reporting a contradiction.
+const ATTEMPTS: u64 = 100;
+
+/// The largest width and height Generate accepts, as requests are answered
+/// one at a time and larger grids would keep the gui busy for too long.
+const MAX_SIZE: usize = 256;
+
+/// The largest form Generate accepts, in bytes.
+const MAX_BODY: usize = 4096;
+
+/// How long a connection may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>tile_collapse</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+label { display: block; margin: 0.4em 0; }
+#message { color: #b00; white-space: pre-wrap; }
+#output img { image-rendering: pixelated; max-width: 100%; }
+</style>
+</head>
+<body>
+<h1>tile_collapse</h1>
+<form id="form">
+<label>Input folder <input name="folder" size="50" required></label>
+<label>Width <input name="width" type="number" min="1" max="{max_size}" value="20"></label>
+<label>Height <input name="height" type="number" min="1" max="{max_size}" value="20"></label>
+<label><input name="periodic" type="checkbox"> Periodic</label>
+<label>Heuristic <select name="heuristic">{heuristics}</select></label>
+<button type="submit">Generate</button>
+<a id="save" download="output.png" hidden>Save</a>
+</form>
+<p id="message"></p>
+<div id="output"></div>
+<script>
+const form = document.getElementById("form");
+const message = document.getElementById("message");
+const save = document.getElementById("save");
+form.addEventListener("submit", async (event) => {
+    event.preventDefault();
+    message.textContent = "Generating...";
+    const response = await fetch("/generate", {
+        method: "POST",
+        body: new URLSearchParams(new FormData(form)),
+    });
+    if (!response.ok) {
+        message.textContent = await response.text();
+        return;
+    }
+    if (save.href) {
+        URL.revokeObjectURL(save.href);
+    }
+    const image = document.createElement("img");
+    image.src = save.href = URL.createObjectURL(await response.blob());
+    document.getElementById("output").replaceChildren(image);
+    message.textContent = "";
+    save.hidden = false;
+});
+</script>
+</body>
+</html>
+"#;
+
+/// Serves the gui on `port` of this machine until the process is stopped.
+pub fn serve(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let port = listener.local_addr()?.port();
+    println!("Open http://127.0.0.1:{port} in a browser");
+    for stream in listener.incoming() {
+        if let Err(err) = stream.and_then(|mut stream| handle(&mut stream, port)) {
+            println!("Connection failed: {err}");
+        }
+    }
+    Ok(())
+}
+
+/// Answers a single request, one connection per request.
+fn handle(stream: &mut TcpStream, port: u16) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&*stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut headers = HashMap::new();
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+        header.clear();
+    }
+
+    let mut request = request_line.split_whitespace();
+    let method = request.next().unwrap_or_default();
+    let target = request.next().unwrap_or("/");
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    if !is_own_request(&headers, port) {
+        return respond(stream, "403 Forbidden", "text/plain", b"Forbidden");
+    }
+    match (method, path) {
+        ("GET", "/") => respond(
+            stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            page().as_bytes(),
+        ),
+        ("POST", "/generate") => {
+            let length = headers
+                .get("content-length")
+                .and_then(|length| length.parse().ok())
+                .unwrap_or(0);
+            if length > MAX_BODY {
+                return respond(stream, "413 Payload Too Large", "text/plain", b"Too large");
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+            match generate(&parse_query(&String::from_utf8_lossy(&body))) {
+                Ok(png) => respond(stream, "200 OK", "image/png", &png),
+                Err(err) => respond_error(stream, &err.to_string()),
+            }
+        }
+        (_, "/" | "/generate") => respond(
+            stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"Method not allowed",
+        ),
+        _ => respond(stream, "404 Not Found", "text/plain", b"Not found"),
+    }
+}
+
+/// Whether a request was sent to the gui by its own page, judging by the
+/// `Host` it was sent to and the `Origin` of the page that sent it.
+///
+/// Checking `Host` keeps out pages that rebind their own domain name to this
+/// machine, and checking `Origin` keeps out form posts from other pages.
+fn is_own_request(headers: &HashMap<String, String>, port: u16) -> bool {
+    let own_host =
+        |host: &str| host == format!("127.0.0.1:{port}") || host == format!("localhost:{port}");
+    headers.get("host").is_some_and(|host| own_host(host))
+        && headers
+            .get("origin")
+            .is_none_or(|origin| origin.strip_prefix("http://").is_some_and(own_host))
+}
+
+/// The page with a choice for every heuristic.
+fn page() -> String {
+    let heuristics: String = Heuristic::value_variants()
+        .iter()
+        .filter_map(|heuristic| heuristic.to_possible_value())
+        .map(|value| format!("<option>{}</option>", value.get_name()))
+        .collect();
+    PAGE.replace("{heuristics}", &heuristics)
+        .replace("{max_size}", &MAX_SIZE.to_string())
+}
+
+/// Runs the model the `form` describes, returning its output encoded as a PNG.
+fn generate(form: &HashMap<String, String>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+    let folder = field("folder");
+    let width: usize = field("width")
+        .parse()
+        .map_err(|_| "Width must be a whole number")?;
+    let height: usize = field("height")
+        .parse()
+        .map_err(|_| "Height must be a whole number")?;
+    if width == 0 || height == 0 {
+        Err("Width and height must be at least 1")?;
+    }
+    if width > MAX_SIZE || height > MAX_SIZE {
+        Err(format!("Width and height can be at most {MAX_SIZE}"))?;
+    }
+    let periodic = field("periodic") == "on";
+    let heuristic = Heuristic::from_str(field("heuristic"), true)?;
+
+    let config = read_config(folder)?;
+    let mut model = SimpleTiled::new(config, folder, width, height, periodic, heuristic)?;
+    crate::print_warnings(&mut model);
+    let master_seed = rand::random();
+    if !(0..ATTEMPTS).any(|attempt| model.run(attempt_seed(master_seed, 0, attempt), usize::MAX)) {
+        Err(format!("All {ATTEMPTS} seeds ran into a contradiction"))?;
+    }
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgba8(model.render()?)
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+/// Splits a form encoded the way of a query string into its decoded fields.
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+/// Undoes the form encoding of a query string part, `+` for spaces and `%XX`
+/// for any other byte.
+fn decode(part: &str) -> String {
+    let mut bytes = Vec::with_capacity(part.len());
+    let mut rest = part.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(decoded) => {
+                        bytes.push(decoded);
+                        rest = &rest[2..];
+                    }
+                    None => bytes.push(b'%'),
+                }
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn respond_error(stream: &mut TcpStream, message: &str) -> io::Result<()> {
+    respond(
+        stream,
+        "422 Unprocessable Entity",
+        "text/plain",
+        message.as_bytes(),
+    )
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+#[test]
+fn form_fields_are_decoded() {
+    let form = parse_query("folder=tiles%2Fknots+2&width=20&periodic=on&bad=%zz");
+    assert_eq!(form["folder"], "tiles/knots 2");
+    assert_eq!(form["width"], "20");
+    assert_eq!(form["periodic"], "on");
+    assert_eq!(form["bad"], "%zz");
+    assert!(parse_query("").is_empty());
+}
+
+#[test]
+fn requests_from_other_sites_are_refused() {
+    let headers = |pairs: &[(&str, &str)]| {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>()
+    };
+    assert!(is_own_request(
+        &headers(&[("host", "127.0.0.1:7878")]),
+        7878
+    ));
+    assert!(is_own_request(
+        &headers(&[
+            ("host", "localhost:7878"),
+            ("origin", "http://localhost:7878")
+        ]),
+        7878
+    ));
+    assert!(!is_own_request(&headers(&[]), 7878));
+    assert!(!is_own_request(
+        &headers(&[("host", "evil.example:7878")]),
+        7878
+    ));
+    assert!(!is_own_request(
+        &headers(&[("host", "127.0.0.1:7879")]),
+        7878
+    ));
+    assert!(!is_own_request(
+        &headers(&[
+            ("host", "127.0.0.1:7878"),
+            ("origin", "http://evil.example")
+        ]),
+        7878
+    ));
+    let form = HashMap::from([
+        ("folder".to_string(), "knot".to_string()),
+        ("width".to_string(), (MAX_SIZE + 1).to_string()),
+        ("height".to_string(), "1".to_string()),
+    ]);
+    assert!(generate(&form).is_err());
+}
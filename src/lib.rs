@@ -1,22 +1,372 @@
-use std::{ffi::OsStr, path::Path};
+use std::{collections::HashMap, error::Error, ffi::OsStr, fmt::Display, path::Path};
 
-use serde::Deserialize;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Debug)]
+pub use tile::{Symmetry, Tile};
+
+/// The contents of a tileset's `config.toml`, also buildable in code with
+/// [`Config::add_tile`] and [`Config::add_neighbor`].
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct Config {
-    tiles: Vec<tile::Tile>,
+    tiles: Vec<Tile>,
     neighbors: Vec<Neighbor>,
+    /// IDs to export tiles as, keyed by tile name like `"wall"` or by variant
+    /// like `"wall 1"`, which takes precedence
+    #[serde(default)]
+    mapping: HashMap<String, MappedId>,
+    /// Named flags like `walkable` in the `[flags]` table, keyed by tile or by
+    /// variant like mapping, e.g. `empty = { walkable = true }`
+    #[serde(default)]
+    flags: HashMap<String, HashMap<String, bool>>,
+    /// Named groups of tiles in the `[groups]` table that neighbor rules can use
+    /// in place of a tile, e.g. `water = ["sea", "shore 1"]`
+    #[serde(default)]
+    groups: HashMap<String, Vec<String>>,
+    /// Splits each tile's weight evenly among its variants, see [`Tile::weight`]
+    #[serde(default)]
+    normalize_weights: bool,
+    /// Reads tile weights as natural logarithms, see [`Tile::weight`]
+    #[serde(default)]
+    weights_are_log: bool,
+    /// The smallest width or height in pixels tiles may have, 1 by default
+    min_tile_size: Option<u32>,
+    /// Drops the adjacencies whose touching edges differ in any pixel
+    #[serde(default)]
+    verify_edges: bool,
+    /// Derives the vertical and mirrored versions of every neighbor rule, true
+    /// by default
+    auto_symmetry_neighbors: Option<bool>,
+}
+
+/// An ID a tile maps to in the `[mapping]` table of a config, e.g. a game
+/// engine's object ID.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum MappedId {
+    Number(i64),
+    Name(String),
+}
+
+impl Display for MappedId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MappedId::Number(id) => write!(f, "{id}"),
+            MappedId::Name(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+impl Config {
+    /// A config without any tiles or neighbors.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_tile(&mut self, tile: Tile) -> &mut Self {
+        self.tiles.push(tile);
+        self
+    }
+
+    /// Allows `right` to the right of `left`, both given as in `config.toml`,
+    /// e.g. `"corner 1"` or `"*"`.
+    pub fn add_neighbor(&mut self, left: &str, right: &str) -> &mut Self {
+        self.neighbors.push(Neighbor::new(left, right));
+        self
+    }
+
+    pub fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+
+    pub fn neighbors(&self) -> &[Neighbor] {
+        &self.neighbors
+    }
+
+    /// Whether each variant gets `weight / cardinality` instead of the whole
+    /// weight of its tile, see [`Tile::weight`].
+    pub fn set_normalize_weights(&mut self, normalize: bool) -> &mut Self {
+        self.normalize_weights = normalize;
+        self
+    }
+
+    /// Whether tile weights are natural logarithms, so `0` is the usual weight
+    /// and every `+1` makes a tile `e` times as likely.
+    pub fn set_weights_are_log(&mut self, log: bool) -> &mut Self {
+        self.weights_are_log = log;
+        self
+    }
+
+    /// Makes building a model fail if tiles are narrower or shorter than `pixels`.
+    pub fn set_min_tile_size(&mut self, pixels: u32) -> &mut Self {
+        self.min_tile_size = Some(pixels);
+        self
+    }
+
+    /// Whether to drop the adjacencies, including those derived from the
+    /// symmetries, whose touching edge pixels don't match exactly. This catches
+    /// tiles with the wrong symmetry but needs tiles drawn to line up.
+    pub fn set_verify_edges(&mut self, verify: bool) -> &mut Self {
+        self.verify_edges = verify;
+        self
+    }
+
+    /// Allows `bottom` below `top`, only valid without auto symmetry neighbors.
+    pub fn add_vertical_neighbor(&mut self, top: &str, bottom: &str) -> &mut Self {
+        self.neighbors.push(Neighbor {
+            vertical: true,
+            up_down: true,
+            ..Neighbor::new(top, bottom)
+        });
+        self
+    }
+
+    /// Whether each neighbor rule also allows its vertical and mirrored
+    /// versions, which is the default. Without them a rule only allows its
+    /// tiles side by side in exactly the orientations given, and tiles can only
+    /// be stacked as allowed by `up`/`down` rules or those with `vertical = true`.
+    pub fn set_auto_symmetry_neighbors(&mut self, auto: bool) -> &mut Self {
+        self.auto_symmetry_neighbors = Some(auto);
+        self
+    }
+
+    /// Maps `name`, a tile or a single variant like `"wall 1"`, to `id`.
+    pub fn add_mapping(&mut self, name: &str, id: MappedId) -> &mut Self {
+        self.mapping.insert(name.to_string(), id);
+        self
+    }
+
+    /// Sets `flag` of `name`, a tile or a single variant like `"wall 1"`, see
+    /// [`model::SimpleTiled::flag_grid`].
+    pub fn add_flag(&mut self, name: &str, flag: &str, value: bool) -> &mut Self {
+        self.flags
+            .entry(name.to_string())
+            .or_default()
+            .insert(flag.to_string(), value);
+        self
+    }
+
+    /// Names a group of tiles, each given as in `config.toml`, that neighbor rules
+    /// can reference to allow every tile of the group.
+    pub fn add_group(&mut self, name: &str, members: &[&str]) -> &mut Self {
+        self.groups.insert(
+            name.to_string(),
+            members.iter().map(ToString::to_string).collect(),
+        );
+        self
+    }
+
+    /// Replaces every tile whose name is a glob pattern (e.g. `"terrain/*.png"`)
+    /// with one tile per matching file in `folder`, sharing its symmetry and weight.
+    pub fn expand_tile_globs(&mut self, folder: &str) -> Result<(), Box<dyn Error>> {
+        let mut tiles = Vec::with_capacity(self.tiles.len());
+        for tile in self.tiles.drain(..) {
+            if !tile.name.contains(['*', '?', '[']) {
+                tiles.push(tile);
+                continue;
+            }
+
+            let pattern = format!("{}/{}", glob::Pattern::escape(folder), tile.name);
+            let mut matched = false;
+            for path in glob::glob(&pattern)? {
+                let path = path?;
+                let name = path
+                    .strip_prefix(folder)?
+                    .to_str()
+                    .ok_or("Tile path is not valid UTF-8")?
+                    .to_string();
+                tiles.push(Tile {
+                    name,
+                    symmetry: tile.symmetry.clone(),
+                    weight: tile.weight,
+                    recolor: tile.recolor.clone(),
+                });
+                matched = true;
+            }
+            if !matched {
+                Err(format!("Tile pattern '{}' matched no files", tile.name))?;
+            }
+        }
+        self.tiles = tiles;
+        Ok(())
+    }
+
+    /// Writes the `tiles` table of this config with the weights taken from
+    /// `weights`, e.g. as returned by [`model::SimpleTiled::example_weights`].
+    /// Tiles without an entry keep their weight. The `weights` are per variant,
+    /// so with `normalize_weights` they are multiplied by the cardinality of
+    /// their tile, and with `weights_are_log` they are written as logarithms.
+    pub fn weight_block(&self, weights: &[(String, f64)]) -> Result<String, Box<dyn Error>> {
+        let mut block = String::from("tiles = [\n");
+        for tile in &self.tiles {
+            let (name, symmetry) = tile.name_and_symmetry()?;
+            let weight = weights
+                .iter()
+                .find(|(base, _)| base == name)
+                .map(|&(_, weight)| {
+                    if self.normalize_weights {
+                        weight * symmetry.cardinality() as f64
+                    } else {
+                        weight
+                    }
+                })
+                .map(|weight| {
+                    if self.weights_are_log {
+                        weight.ln()
+                    } else {
+                        weight
+                    }
+                })
+                .or(tile.weight);
+            block += &format!("\t{{ name = \"{}\"", tile.name);
+            if let Some(symmetry) = &tile.symmetry {
+                block += &format!(", symmetry = '{symmetry}'");
+            }
+            if let Some(weight) = weight {
+                block += &format!(", weight = {weight:?}");
+            }
+            if !tile.recolor.is_empty() {
+                block += &format!(", recolor = {:?}", tile.recolor);
+            }
+            block += " },\n";
+        }
+        block += "]\n";
+        Ok(block)
+    }
 }
 
 mod tile {
-    use super::Deserialize;
+    use std::str::FromStr;
+
     use image::DynamicImage;
+    use serde::{Deserialize, Serialize};
+
+    use crate::name_from_file_name;
+
+    /// How a tile maps onto itself under rotations and reflections, named after
+    /// the letter that does the same.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum Symmetry {
+        X,
+        L,
+        T,
+        I,
+        /// `\`, symmetric about its main diagonal
+        Diagonal,
+        F,
+        Z,
+    }
+
+    impl Symmetry {
+        /// Every symmetry, in the order their codes are listed in errors.
+        pub const ALL: [Symmetry; 7] = [
+            Symmetry::X,
+            Symmetry::L,
+            Symmetry::T,
+            Symmetry::I,
+            Symmetry::Diagonal,
+            Symmetry::F,
+            Symmetry::Z,
+        ];
+
+        /// The code of this symmetry in `config.toml` and file names.
+        pub fn code(self) -> &'static str {
+            match self {
+                Symmetry::X => "X",
+                Symmetry::L => "L",
+                Symmetry::T => "T",
+                Symmetry::I => "I",
+                Symmetry::Diagonal => "\\",
+                Symmetry::F => "F",
+                Symmetry::Z => "Z",
+            }
+        }
+
+        /// How many distinct tiles a tile with this symmetry expands to.
+        pub fn cardinality(self) -> usize {
+            match self {
+                Symmetry::X => 1,
+                Symmetry::I | Symmetry::Diagonal => 2,
+                Symmetry::L | Symmetry::T | Symmetry::Z => 4,
+                Symmetry::F => 8,
+            }
+        }
+    }
+
+    impl FromStr for Symmetry {
+        type Err = String;
+
+        fn from_str(code: &str) -> Result<Self, Self::Err> {
+            Self::ALL
+                .into_iter()
+                .find(|symmetry| symmetry.code() == code)
+                .ok_or_else(|| {
+                    let codes: Vec<&str> = Self::ALL.iter().map(|s| s.code()).collect();
+                    format!(
+                        "Unrecognized symmetry '{code}', expected one of {}",
+                        codes.join(" ")
+                    )
+                })
+        }
+    }
 
-    #[derive(Deserialize, Debug)]
+    #[derive(Deserialize, Debug, Clone)]
     pub struct Tile {
         pub name: String,
-        pub symmetry: String,
+        pub symmetry: Option<String>,
+        /// How likely this tile is picked, 1 by default.
+        ///
+        /// Every variant carries the whole weight, so an `F` tile with its 8
+        /// variants is picked 8 times as often as an `X` tile of the same weight.
+        /// Set `normalize_weights = true` in the config to split the weight among
+        /// the variants instead. With `weights_are_log = true` the weight is
+        /// `ln` of that, 0 by default.
         pub weight: Option<f64>,
+        /// Hue shifts in degrees, each adding a recolored copy of this tile named
+        /// like `"grass~120"`.
+        ///
+        /// A copy has every symmetry variant of the tile, recolored, and the same
+        /// weight. It may sit wherever the tile may, next to the tile and its other
+        /// copies too, so neighbor rules only name the tile. `[mapping]` and
+        /// `[flags]` entries of the tile apply to its copies unless they have their
+        /// own.
+        #[serde(default)]
+        pub recolor: Vec<i32>,
+    }
+
+    impl Tile {
+        /// A tile read from the file `name`, without a symmetry or weight
+        /// falling back to the defaults like a missing key in `config.toml`.
+        pub fn new(name: &str, symmetry: Option<&str>, weight: Option<f64>) -> Self {
+            Self {
+                name: name.to_string(),
+                symmetry: symmetry.map(ToOwned::to_owned),
+                weight,
+                recolor: vec![],
+            }
+        }
+
+        /// The name neighbor rules use for this tile and its symmetry code.
+        ///
+        /// Without a `symmetry` field the code is the last dot separated part of
+        /// the file name, so `corner.L.png` is the `L` tile `corner`. File names
+        /// without such a part are `X` tiles.
+        pub fn name_and_symmetry(&self) -> Result<(&str, Symmetry), String> {
+            let stem = name_from_file_name(&self.name)?;
+            if let Some(symmetry) = &self.symmetry {
+                let symmetry = symmetry
+                    .parse()
+                    .map_err(|err| format!("Tile '{}': {err}", self.name))?;
+                return Ok((stem, symmetry));
+            }
+            match stem.rsplit_once('.') {
+                Some((name, code)) => code
+                    .parse()
+                    .map(|symmetry| (name, symmetry))
+                    .map_err(|err| format!("{err} in file name '{}'", self.name)),
+                None => Ok((stem, Symmetry::X)),
+            }
+        }
     }
 
     #[derive(Clone, Debug)]
@@ -33,48 +383,356 @@ mod tile {
         pub fn fliph(&mut self) {
             self.image = self.image.fliph();
         }
+
+        /// Mirrors the tile about its main diagonal, from the top left corner to
+        /// the bottom right one.
+        pub fn transpose(&mut self) {
+            self.image = self.image.rotate90().fliph();
+        }
+
+        /// Returns this tile followed by the rotated and mirrored copies making up
+        /// the rest of the variants of a tile with `symmetry`.
+        pub fn variants(&self, symmetry: Symmetry) -> Vec<TileObject> {
+            if symmetry == Symmetry::F {
+                return self.dihedral_variants();
+            }
+            let mut variants = vec![self.clone()];
+            for i in 1..symmetry.cardinality() {
+                let mut new_tile;
+                if i == 2 && symmetry == Symmetry::Z {
+                    // Rotating a `Z` tile twice gives it back, so its
+                    // remaining variants are the mirror image and its rotation
+                    new_tile = variants[0].clone();
+                    new_tile.fliph();
+                } else if i <= 3 {
+                    new_tile = variants[i - 1].clone();
+                    new_tile.rotate_90();
+                } else {
+                    new_tile = variants[i - 4].clone();
+                    new_tile.fliph();
+                }
+                variants.push(new_tile);
+            }
+            variants
+        }
+
+        /// The 8 variants of an `F` tile in the order [`TileObject::variants`]
+        /// gives them, each at most two flips or a transpose away from this tile
+        /// instead of chaining quarter turns.
+        fn dihedral_variants(&self) -> Vec<TileObject> {
+            let with = |image: DynamicImage| TileObject {
+                image,
+                weight: self.weight,
+            };
+            let mut transposed = self.clone();
+            transposed.transpose();
+            vec![
+                self.clone(),
+                with(transposed.image.flipv()),
+                with(self.image.rotate180()),
+                with(transposed.image.fliph()),
+                with(self.image.fliph()),
+                with(transposed.image.rotate180()),
+                with(self.image.flipv()),
+                transposed,
+            ]
+        }
+    }
+
+    /// A tile as listed in the config, which expands to `cardinality` consecutive
+    /// tiles starting at `first`.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub(crate) struct BaseTile {
+        pub name: String,
+        pub symmetry: Symmetry,
+        pub first: usize,
+        pub cardinality: usize,
+    }
+}
+/// A neighbor rule, written as `{ left="a", right="b" }` or, for a vertical
+/// rule, as `{ up="a", down="b" }`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "NeighborEntry")]
+pub struct Neighbor {
+    pub left: String,
+    pub right: String,
+    /// Puts `left` on top of `right` instead, only valid with
+    /// `auto_symmetry_neighbors = false`
+    pub vertical: bool,
+    // Written as `{ up, down }`, so messages show the rule the same way
+    up_down: bool,
+}
+
+/// The forms a neighbor rule can take in `config.toml`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NeighborEntry {
+    Horizontal {
+        left: String,
+        right: String,
+        #[serde(default)]
+        vertical: bool,
+    },
+    Vertical {
+        up: String,
+        down: String,
+    },
+}
+
+impl From<NeighborEntry> for Neighbor {
+    fn from(entry: NeighborEntry) -> Self {
+        match entry {
+            NeighborEntry::Horizontal {
+                left,
+                right,
+                vertical,
+            } => Self {
+                left,
+                right,
+                vertical,
+                up_down: false,
+            },
+            NeighborEntry::Vertical { up, down } => Self {
+                left: up,
+                right: down,
+                vertical: true,
+                up_down: true,
+            },
+        }
+    }
+}
+
+impl Neighbor {
+    pub fn new(left: &str, right: &str) -> Self {
+        Self {
+            left: left.to_string(),
+            right: right.to_string(),
+            vertical: false,
+            up_down: false,
+        }
     }
 }
-#[derive(Deserialize, Debug)]
-struct Neighbor {
-    left: String,
-    right: String,
+
+/// Shows the rule in the form it takes in `config.toml`.
+impl Display for Neighbor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (left, right) = (&self.left, &self.right);
+        if self.up_down {
+            write!(f, "{{ up=\"{left}\", down=\"{right}\" }}")
+        } else if self.vertical {
+            write!(f, "{{ left=\"{left}\", right=\"{right}\", vertical=true }}")
+        } else {
+            write!(f, "{{ left=\"{left}\", right=\"{right}\" }}")
+        }
+    }
 }
 
 pub mod model {
-    use std::{collections::HashMap, error::Error, fmt::Display, path::Path};
+    use std::{
+        cell::OnceCell,
+        collections::{BTreeMap, HashMap, HashSet},
+        error::Error,
+        fmt::Display,
+        fs::File,
+        io::{BufReader, BufWriter, Write},
+        path::Path,
+    };
 
     use clap::clap_derive::ArgEnum;
-    use image::{GenericImage, ImageBuffer};
+    use image::{
+        codecs::gif::{GifEncoder, Repeat},
+        imageops, Delay, DynamicImage, Frame, GenericImage, ImageBuffer, ImageFormat, Rgba,
+        RgbaImage,
+    };
     use indicatif::{ProgressBar, ProgressStyle};
     use rand::prelude::*;
     use rand_chacha::ChaCha8Rng;
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        derive_seed, random_from_distr,
+        tile::{BaseTile, TileObject},
+        unit_f64, Config, MappedId, Neighbor, Symmetry,
+    };
+
+    /// A side of a cell, in the order used to index the propagator.
+    #[derive(PartialEq, Eq, Debug, Clone, Copy)]
+    pub enum Direction {
+        Left,
+        Down,
+        Right,
+        Up,
+    }
 
-    use crate::{name_from_file_name, random_from_distr, tile::TileObject, Config};
+    impl Direction {
+        /// Every direction, in index order.
+        pub const ALL: [Direction; 4] = [
+            Direction::Left,
+            Direction::Down,
+            Direction::Right,
+            Direction::Up,
+        ];
+
+        /// The index of this direction into the propagator and `compatible`.
+        pub fn index(self) -> usize {
+            self as usize
+        }
+
+        /// The `(dx, dy)` step to the neighboring cell, with y growing downwards.
+        pub fn delta(self) -> (isize, isize) {
+            match self {
+                Direction::Left => (-1, 0),
+                Direction::Down => (0, 1),
+                Direction::Right => (1, 0),
+                Direction::Up => (0, -1),
+            }
+        }
 
-    static OPPOSITE: [usize; 4] = [2, 3, 0, 1];
-    static DX: [isize; 4] = [-1, 0, 1, 0];
-    static DY: [isize; 4] = [0, 1, 0, -1];
+        pub fn opposite(self) -> Self {
+            match self {
+                Direction::Left => Direction::Right,
+                Direction::Down => Direction::Up,
+                Direction::Right => Direction::Left,
+                Direction::Up => Direction::Down,
+            }
+        }
+    }
 
     #[derive(PartialEq, Debug, ArgEnum, Clone)]
     pub enum Heuristic {
         Entropy,
         MRV,
         ScanLine,
+        /// Entropy, preferring the cells with the most collapsed neighbors among
+        /// those tied for the lowest entropy. Costs a second pass over the cells
+        /// and a look at the neighbors of every tied cell per observation.
+        Agreement,
+    }
+
+    /// How MRV picks among the cells tied for the fewest possible tiles.
+    #[derive(PartialEq, Eq, Debug, ArgEnum, Clone, Copy)]
+    pub enum TieBreak {
+        /// Any of them, by the random tie breaks of the run
+        Random,
+        /// The one closest to the center of the grid
+        Center,
+        /// The one with the most collapsed neighbors
+        Observed,
+    }
+
+    /// Opposite edges of a non-periodic output that are kept identical, so that
+    /// copies of it can be laid out overlapping by one cell.
+    #[derive(PartialEq, Eq, Debug, ArgEnum, Clone, Copy)]
+    pub enum EdgeMatching {
+        /// The left and right columns match
+        Horizontal,
+        /// The top and bottom rows match
+        Vertical,
+        /// Both pairs of edges match
+        Both,
+    }
+
+    /// The order in which grid accessors list cells.
+    #[derive(PartialEq, Eq, Debug, Clone, Copy)]
+    pub enum GridOrder {
+        /// Row by row from the top, each row left to right
+        RowMajor,
+        /// Column by column from the left, each column top to bottom
+        ColumnMajor,
+        /// Row by row from the bottom, each row left to right
+        YUp,
+    }
+
+    /// What a run does once propagation leaves a cell without any possible tile.
+    #[derive(PartialEq, Eq, Debug, ArgEnum, Clone, Copy)]
+    pub enum Recovery {
+        /// Fail the run so it can be restarted with another seed
+        Restart,
+        /// Reset the contradicted cell and its neighbors and carry on, see
+        /// [`SimpleTiled::set_recovery`]
+        ResetCluster,
+    }
+
+    /// How far above the lowest entropy a cell still counts as tied for it under
+    /// [`Heuristic::Agreement`].
+    const AGREEMENT_TOLERANCE: f64 = 1e-9;
+
+    /// How many observations at the start of a run [`SimpleTiled::set_adaptive_retries`]
+    /// learns from.
+    const OPENING_OBSERVATIONS: usize = 4;
+
+    /// How long every frame of [`SimpleTiled::save_animation`] shows.
+    const ANIMATION_DELAY_MS: u32 = 100;
+
+    /// Tiles with a side shorter than this many pixels are most likely a mistake
+    /// and get a warning.
+    const SMALL_TILE_SIZE: u32 = 4;
+
+    /// A mirror symmetry imposed on the whole output.
+    #[derive(PartialEq, Debug, ArgEnum, Clone, Copy)]
+    pub enum OutputSymmetry {
+        /// The right half mirrors the left half
+        LeftRight,
+        /// The bottom half mirrors the top half
+        TopBottom,
+        /// Each quadrant mirrors its neighbors
+        FourFold,
+    }
+
+    /// Identifies the kind of a [`Model`] behind a trait object.
+    #[derive(PartialEq, Eq, Debug, Clone, Copy)]
+    pub enum ModelKind {
+        /// A [`SimpleTiled`] model
+        SimpleTiled,
+        /// An [`overlapping::OverlappingModel`]
+        Overlapping,
+        /// A [`blocks::BlockModel`]
+        Blocks,
     }
 
     pub trait Model {
+        /// Clears the model and collapses it with `seed`, making at most `limit`
+        /// observations.
+        ///
+        /// Returns `false` if a contradiction was reached. Once every cell has
+        /// collapsed the result is kept for [`Model::save`]. Otherwise, after a
+        /// contradiction or when `limit` runs out, no cell counts as observed and
+        /// the partially collapsed state is kept for inspection until the next run.
         fn run(&mut self, seed: u64, limit: usize) -> bool;
+        /// Writes the output image to `path`, see [`Model::render`].
         fn save(&self, path: &Path) -> Result<(), Box<dyn Error>>;
+        /// Builds the output image in memory, erroring with "Model is not fully
+        /// rendered" unless every cell has collapsed.
+        fn render(&self) -> Result<RgbaImage, Box<dyn Error>>;
+        /// Which kind of model this is, for callers that need to branch on it.
+        fn kind(&self) -> ModelKind;
+    }
+
+    /// A closure scaling tile weights, see [`SimpleTiled::set_weight_schedule`].
+    struct WeightSchedule(Box<dyn Fn(usize, f64) -> f64>);
+
+    impl std::fmt::Debug for WeightSchedule {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("WeightSchedule")
+        }
     }
 
     #[derive(Debug)]
     pub struct SimpleTiled {
         tiles: Vec<TileObject>,
 
+        base_tiles: Vec<BaseTile>,
+
         tile_names: Vec<String>,
+        // The `[mapping]` ID of every tile, if the config has one
+        mapped_ids: Option<Vec<MappedId>>,
+        // The `[flags]` of every tile
+        tile_flags: Vec<HashMap<String, bool>>,
 
-        tile_size: usize,
+        // Pixel size of every tile, rectangular tiles only stay rectangular
+        // under X symmetry or under the flips of the other symmetries
+        tile_width: usize,
+        tile_height: usize,
 
         // Model.cs stuff
         wave: Vec<Vec<bool>>,
@@ -105,8 +763,231 @@ pub mod model {
         entropies: Vec<f64>,
 
         heuristic: Heuristic,
+
+        stats: RunStats,
+
+        background: [u8; 4],
+        edge_blend: u32,
+
+        // Filled in on first use and dropped whenever the tile images change
+        average_colors: OnceCell<Vec<[u8; 4]>>,
+
+        // Cells restricted to a subset of tiles at the start of every run
+        constraints: Vec<(usize, Vec<bool>)>,
+        // Whether each cell is generated at all, see `set_mask`
+        mask: Option<Vec<bool>>,
+        // The tiles of every cap with the fraction of the grid they may cover
+        tile_caps: Vec<(Vec<bool>, f64)>,
+        // A weight multiplier for every tile of every cell, see `set_guide`
+        guide: Option<Vec<Vec<f64>>>,
+
+        stack_warning: Option<usize>,
+        // Warnings not yet taken by `take_warnings`
+        warnings: Vec<String>,
+        // Whether `run` draws a progress bar in the terminal
+        progress: bool,
+
+        // The variant each tile turns into under every rotation and reflection
+        action: Vec<[usize; 8]>,
+        output_symmetry: Option<OutputSymmetry>,
+        edge_matching: Option<EdgeMatching>,
+
+        required_tiles: Vec<(String, Vec<bool>)>,
+
+        recovery: Recovery,
+        adaptive_retries: bool,
+
+        batch_observe: usize,
+        stop_entropy: Option<f64>,
+        weight_schedule: Option<WeightSchedule>,
+
+        tie_break: TieBreak,
+        deterministic: bool,
+        frontier_only: bool,
+        // Cells next to a collapsed cell, only tracked when `frontier_only` is set
+        frontier: Vec<bool>,
+        // How often each (cell, tile) opening observation preceded a failed run
+        failure_memory: HashMap<(usize, usize), u32>,
+        // The (cell, tile) opening observations of the current run
+        opening: Vec<(usize, usize)>,
+        // The first cell propagation left without a possible tile
+        contradiction: Option<usize>,
+        // Noise added to each cell's entropy to break ties, drawn once per run so
+        // the chosen cell only depends on the seed and the wave
+        tie_breaks: Vec<f64>,
+        // Every change to the wave since the run started, when `record_events` is set
+        record_events: bool,
+        events: Vec<SolveEvent>,
+    }
+
+    /// A rule broken by an image checked with [`SimpleTiled::verify_image`].
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub enum Violation {
+        /// The cell at `(x, y)` doesn't look like any tile
+        UnknownTile { x: usize, y: usize },
+        /// The cell at `(x, y)` can't sit next to its neighbor in `direction`
+        Adjacency {
+            x: usize,
+            y: usize,
+            direction: Direction,
+        },
+    }
+
+    impl Display for Violation {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Violation::UnknownTile { x, y } => {
+                    write!(f, "Cell ({x}, {y}) doesn't match any tile")
+                }
+                Violation::Adjacency { x, y, direction } => write!(
+                    f,
+                    "Cell ({x}, {y}) can't be next to its neighbor to the {direction:?}"
+                ),
+            }
+        }
+    }
+
+    /// What a single [`SimpleTiled::step`] did.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum StepResult {
+        /// A cell was observed and propagated, more cells are left
+        Progressed,
+        /// Every cell has collapsed
+        Finished,
+        /// The last observation left some cell without a possible tile
+        Contradiction,
+    }
+
+    /// A single change to the wave during a run, recorded with
+    /// [`SimpleTiled::set_record_events`].
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum SolveEvent {
+        /// `cell` was collapsed to `tile`, the bans doing so follow
+        Observe { cell: usize, tile: usize },
+        /// `tile` stopped being possible at `cell`
+        Ban { cell: usize, tile: usize },
+        /// Every tile became possible again at `cells` to recover from a
+        /// contradiction, see [`Recovery::ResetCluster`]
+        Reset { cells: Vec<usize> },
+    }
+
+    /// Everything [`SimpleTiled::new`] derives from a config and its images,
+    /// independent of the output size.
+    struct Tileset {
+        tiles: Vec<TileObject>,
+        base_tiles: Vec<BaseTile>,
+        tile_names: Vec<String>,
+        mapped_ids: Option<Vec<MappedId>>,
+        tile_flags: Vec<HashMap<String, bool>>,
+        propagator: Vec<Vec<Vec<usize>>>,
+        action: Vec<[usize; 8]>,
+    }
+
+    const COMPILED_VERSION: u32 = 4;
+
+    /// The on-disk form of a [`Tileset`], with images stored as raw RGBA pixels.
+    #[derive(Serialize, Deserialize)]
+    struct CompiledTileset {
+        version: u32,
+        tiles: Vec<(u32, u32, Vec<u8>, f64)>,
+        base_tiles: Vec<BaseTile>,
+        tile_names: Vec<String>,
+        // Untagged IDs can't be read back from bincode, so numbers are `Ok`
+        mapped_ids: Option<Vec<Result<i64, String>>>,
+        tile_flags: Vec<HashMap<String, bool>>,
+        propagator: Vec<Vec<Vec<usize>>>,
+        action: Vec<[usize; 8]>,
+    }
+
+    /// The mutable solving state of a [`SimpleTiled`], taken with
+    /// [`SimpleTiled::snapshot`] and put back with [`SimpleTiled::restore`].
+    #[derive(Clone, Debug)]
+    pub struct ModelSnapshot {
+        wave: Vec<Vec<bool>>,
+        compatible: Vec<Vec<Vec<isize>>>,
+        observed: Vec<Option<usize>>,
+        stack: Vec<(usize, usize)>,
+        observed_so_far: usize,
+        sums_of_ones: Vec<isize>,
+        sums_of_weights: Vec<f64>,
+        sums_of_weight_log_weights: Vec<f64>,
+        entropies: Vec<f64>,
+        contradiction: Option<usize>,
+        frontier: Vec<bool>,
+        events: Vec<SolveEvent>,
+    }
+
+    /// Counters collected over a single run of a model.
+    #[derive(Debug, Clone, Default)]
+    pub struct RunStats {
+        pub observations: usize,
+        pub contradictions: usize,
+        pub peak_stack_depth: usize,
+        /// Contradictions recovered from by [`Recovery::ResetCluster`]
+        pub local_resets: usize,
+        /// Observations undone by [`SimpleTiled::run_with_backtracking`]
+        pub backtracks: usize,
+        /// The required tile that could not be placed, if that ended the run
+        pub unplaced_tile: Option<String>,
+        /// The `(x, y)` of the cell left without a possible tile, if that ended
+        /// the run
+        pub contradiction: Option<(usize, usize)>,
+    }
+
+    /// Averages over the trials of [`SimpleTiled::benchmark_solvability`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct SolvabilityReport {
+        pub success_rate: f64,
+        pub avg_observations: f64,
+        pub avg_contradictions: f64,
+    }
+
+    /// Only the tiles a solved grid uses, see [`SimpleTiled::palette`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Palette {
+        /// The used tiles side by side in a single row, in tile index order
+        pub image: RgbaImage,
+        /// The palette position of every cell, row by row from the top, `None`
+        /// for cells left out by the mask
+        pub indices: Vec<Vec<Option<usize>>>,
+        /// The name of the tile at each palette position, e.g. `corner 3`
+        pub names: Vec<String>,
+    }
+
+    impl Palette {
+        /// Writes the palette image to `path`, the index map next to it as CSV
+        /// and the position of each tile name as TOML, e.g. `a.png`, `a.csv` and
+        /// `a.toml`. Masked cells are empty fields in the CSV.
+        pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+            self.image.save(path)?;
+            let mut csv = String::new();
+            for row in &self.indices {
+                let row: Vec<String> = row
+                    .iter()
+                    .map(|position| position.map_or(String::new(), |p| p.to_string()))
+                    .collect();
+                csv += &row.join(",");
+                csv += "\n";
+            }
+            std::fs::write(path.with_extension("csv"), csv)?;
+            let positions: BTreeMap<&str, usize> = self
+                .names
+                .iter()
+                .enumerate()
+                .map(|(position, name)| (name.as_str(), position))
+                .collect();
+            std::fs::write(path.with_extension("toml"), toml::to_string(&positions)?)?;
+            Ok(())
+        }
     }
 
+    /// Whether tile `b` may sit next to tile `a` in a direction, see
+    /// [`SimpleTiled::with_adjacency`].
+    type Adjacency<'a> = &'a dyn Fn(usize, Direction, usize) -> bool;
+
+    /// A tile's name, decoded image and weight, see [`SimpleTiled::from_tiles`].
+    pub type TileImage = (String, DynamicImage, f64);
+
     impl SimpleTiled {
         pub fn new(
             config: Config,
@@ -115,66 +996,150 @@ pub mod model {
             height: usize,
             periodic: bool,
             heuristic: Heuristic,
+        ) -> Result<Self, Box<dyn Error>> {
+            let images = Self::load_images(&config, folder)?;
+            Self::build(config, images, width, height, periodic, heuristic, None)
+        }
+
+        /// Builds a model like [`SimpleTiled::new`] from already decoded tile
+        /// images instead of a folder.
+        ///
+        /// `images` holds a `(name, image, weight)` entry for every tile in the
+        /// config, keyed by the tile's name without extension or symmetry suffix.
+        /// The config still supplies symmetries and neighbors, and the weights
+        /// replace the config's, read in log space if `weights_are_log` is set.
+        pub fn from_tiles(
+            config: Config,
+            images: Vec<TileImage>,
+            width: usize,
+            height: usize,
+            periodic: bool,
+            heuristic: Heuristic,
+        ) -> Result<Self, Box<dyn Error>> {
+            Self::build(config, images, width, height, periodic, heuristic, None)
+        }
+
+        /// Opens the images of the config's tiles in `folder`, paired with their
+        /// configured weights.
+        fn load_images(config: &Config, folder: &str) -> Result<Vec<TileImage>, Box<dyn Error>> {
+            let default_weight = if config.weights_are_log { 0. } else { 1. };
+            config
+                .tiles
+                .iter()
+                .map(|tile| {
+                    let (name, _) = tile.name_and_symmetry()?;
+                    let image = image::open(format!("{}/{}", folder, tile.name))?;
+                    Ok((
+                        name.to_string(),
+                        image,
+                        tile.weight.unwrap_or(default_weight),
+                    ))
+                })
+                .collect()
+        }
+
+        /// Builds a model like [`SimpleTiled::new`], but takes the adjacencies
+        /// from `adjacency` instead of the config's neighbors, which are ignored.
+        ///
+        /// `adjacency(a, direction, b)` says whether tile `b` may sit next to tile
+        /// `a` in `direction`, with tiles indexed like [`SimpleTiled::tile_names`],
+        /// so every variant is asked for separately. A pair is only allowed if
+        /// both tiles agree, i.e. `adjacency(b, direction.opposite(), a)` holds too.
+        pub fn with_adjacency(
+            config: Config,
+            folder: &str,
+            width: usize,
+            height: usize,
+            periodic: bool,
+            heuristic: Heuristic,
+            adjacency: impl Fn(usize, Direction, usize) -> bool,
+        ) -> Result<Self, Box<dyn Error>> {
+            let images = Self::load_images(&config, folder)?;
+            Self::build(
+                config,
+                images,
+                width,
+                height,
+                periodic,
+                heuristic,
+                Some(&adjacency),
+            )
+        }
+
+        fn build(
+            config: Config,
+            images: Vec<TileImage>,
+            width: usize,
+            height: usize,
+            periodic: bool,
+            heuristic: Heuristic,
+            adjacency: Option<Adjacency>,
         ) -> Result<Self, Box<dyn Error>> {
             if config.tiles.is_empty() {
                 Err("No tiles in config file")?;
-            } else if config.neighbors.is_empty() {
+            } else if config.neighbors.is_empty() && adjacency.is_none() {
                 Err("No Neighbors in config file")?;
             }
 
+            let mut images: HashMap<String, (DynamicImage, f64)> = images
+                .into_iter()
+                .map(|(name, image, weight)| (name, (image, weight)))
+                .collect();
             let mut tiles = Vec::new();
             let mut tile_names = Vec::new();
+            let mut base_tiles = Vec::new();
 
             let mut action: Vec<[i32; 8]> = Vec::new();
             let mut first_occurence = HashMap::new();
+            let mut cardinalities = HashMap::new();
+            let mut recolors = vec![];
 
             for tile in config.tiles {
+                let (name, symmetry) = tile.name_and_symmetry()?;
+                let name = name.to_string();
+                if first_occurence.contains_key(&name) {
+                    Err(format!(
+                        "Tile '{}' is named '{name}' like an earlier tile, rename one of them",
+                        tile.name
+                    ))?;
+                }
                 let a: fn(i32) -> i32;
                 let b: fn(i32) -> i32;
-                let cardinality: i32;
-                match tile.symmetry.as_bytes()[0] {
-                    b'L' => {
-                        cardinality = 4;
+                let cardinality = symmetry.cardinality() as i32;
+                match symmetry {
+                    Symmetry::L => {
                         a = |i| (i + 1) % 4;
                         b = |i| if i % 2 == 0 { i + 1 } else { i - 1 };
                     }
-                    b'T' => {
-                        cardinality = 4;
+                    Symmetry::T => {
                         a = |i| (i + 1) % 4;
                         b = |i| if i % 2 == 0 { i } else { 4 - i };
                     }
-                    b'I' => {
-                        cardinality = 2;
+                    Symmetry::I => {
                         a = |i| 1 - i;
                         b = |i| i;
                     }
-                    b'\\' => {
-                        cardinality = 2;
+                    Symmetry::Diagonal => {
                         a = |i| 1 - i;
                         b = |i| 1 - i;
                     }
-                    b'F' => {
-                        cardinality = 8;
+                    Symmetry::Z => {
+                        a = |i| i ^ 1;
+                        b = |i| i ^ 2;
+                    }
+                    Symmetry::F => {
                         a = |i| if i < 4 { (i + 1) % 4 } else { 4 + (i - 1) % 4 };
                         b = |i| if i < 4 { i + 4 } else { i - 4 };
                     }
-                    _ => {
-                        cardinality = 1;
+                    Symmetry::X => {
                         a = |i| i;
                         b = |i| i;
                     }
                 }
 
                 let t = action.len();
-                if let Some(path) = Path::new(&tile.name)
-                    .file_stem()
-                    .and_then(std::ffi::OsStr::to_str)
-                    .map(ToOwned::to_owned)
-                {
-                    first_occurence.insert(path, t);
-                } else {
-                    Err("Failed to extract tile name from file")?;
-                }
+                cardinalities.insert(name.clone(), cardinality as usize);
+                first_occurence.insert(name.clone(), t);
 
                 let mut map: [[i32; 8]; 8] = [[0; 8]; 8];
                 for i in 0..cardinality {
@@ -192,95 +1157,183 @@ pub mod model {
                     action.push(map[index]);
                 }
 
-                {
-                    let image = image::open(format!("{}/{}", folder, tile.name))?;
-                    tiles.push(TileObject {
-                        image: image.clone(),
-                        weight: tile.weight.unwrap_or(1.0),
-                    });
-
-                    tile_names.push(format!("{} 0", name_from_file_name(&tile.name)?));
-
-                    for i in 1..cardinality {
-                        if i <= 3 {
-                            let mut new_tile = {
-                                let this = tiles.get(t + i as usize - 1);
-                                match this {
-                                    Some(val) => val,
-                                    None => unreachable!(),
-                                }
-                            }
-                            .clone();
-                            new_tile.rotate_90();
-                            tiles.push(new_tile);
-                        } else if i >= 4 {
-                            let mut new_tile = {
-                                let this = tiles.get(t + i as usize - 4);
-                                match this {
-                                    Some(val) => val,
-                                    None => unreachable!(),
-                                }
-                            }
-                            .clone();
-                            new_tile.fliph();
-                            tiles.push(new_tile);
-                        }
-                        tile_names.push(format!("{} {}", name_from_file_name(&tile.name)?, i));
+                let (image, weight) = images
+                    .remove(&name)
+                    .ok_or_else(|| format!("No image for tile '{name}'"))?;
+                let weight = if config.weights_are_log {
+                    weight.exp()
+                } else {
+                    weight
+                };
+                let base = TileObject {
+                    image,
+                    weight: if config.normalize_weights {
+                        weight / cardinality as f64
+                    } else {
+                        weight
+                    },
+                };
+                let variants = base.variants(symmetry);
+                if let Some(first) = tiles.first().map(|t: &TileObject| &t.image) {
+                    if let Some(variant) = variants.iter().find(|v| {
+                        v.image.width() != first.width() || v.image.height() != first.height()
+                    }) {
+                        Err(format!(
+                            "Tile '{}' has a {}x{} pixel variant but the tileset uses {}x{} pixels",
+                            tile.name,
+                            variant.image.width(),
+                            variant.image.height(),
+                            first.width(),
+                            first.height()
+                        ))?;
                     }
                 }
+                tiles.extend(variants);
+
+                for i in 0..cardinality {
+                    tile_names.push(format!("{} {}", name, i));
+                }
+                for &shift in &tile.recolor {
+                    recolors.push((base_tiles.len(), shift));
+                }
+                base_tiles.push(BaseTile {
+                    name,
+                    symmetry,
+                    first: t,
+                    cardinality: cardinality as usize,
+                });
+            }
+            if let Some(name) = images.keys().next() {
+                Err(format!("Image '{name}' isn't a tile in the config"))?;
             }
-            let num_tiles: usize = action.len();
 
-            let mut dense_propagater = vec![vec![vec![false; num_tiles]; num_tiles]; 4];
-            let mut propagator = vec![vec![vec![]; num_tiles]; 4];
+            // Recolored copies go after every config tile, so the neighbor rules
+            // only ever see the config tiles
+            let mut source: Vec<usize> = (0..action.len()).collect();
+            let mut recolored_from = HashMap::new();
+            for (b, shift) in recolors {
+                let base = base_tiles[b].clone();
+                let first = action.len();
+                let name = format!("{}~{shift}", base.name);
+                if cardinalities.contains_key(&name) {
+                    Err(format!("Tile '{}' recolors by {shift} twice", base.name))?;
+                }
+                for i in 0..base.cardinality {
+                    let original = &tiles[base.first + i];
+                    tiles.push(TileObject {
+                        image: original.image.huerotate(shift),
+                        weight: original.weight,
+                    });
+                    let offset = first as i32 - base.first as i32;
+                    action.push(action[base.first + i].map(|t| t + offset));
+                    source.push(base.first + i);
+                    tile_names.push(format!("{name} {i}"));
+                }
+                cardinalities.insert(name.clone(), base.cardinality);
+                first_occurence.insert(name.clone(), first);
+                recolored_from.insert(name.clone(), base.name.clone());
+                base_tiles.push(BaseTile {
+                    name,
+                    first,
+                    ..base
+                });
+            }
+            let num_tiles: usize = action.len();
 
-            for neighbor in &config.neighbors {
-                // TODO: implement subsets here
-                let left_tile_name: Vec<String> =
-                    neighbor.left.split(' ').map(str::to_string).collect();
-                let right_tile_name: Vec<String> =
-                    neighbor.right.split(' ').map(str::to_string).collect();
-                let left: usize =
-                    action[first_occurence[(&left_tile_name[0])]][if left_tile_name.len() == 1 {
-                        0
-                    } else {
-                        left_tile_name[1].parse()?
-                    }]
-                    .try_into()?;
-                let down = action[left][1] as usize;
-                let right: usize =
-                    action[first_occurence[&right_tile_name[0]]][if right_tile_name.len() == 1 {
-                        0
-                    } else {
-                        right_tile_name[1].parse()?
-                    }]
-                    .try_into()?;
-                let up = action[right][1] as usize;
+            let first = tiles.first().ok_or("No tiles in config file")?;
+            let (tile_width, tile_height) = (first.image.width(), first.image.height());
+            let min_tile_size = config.min_tile_size.unwrap_or(1);
+            let mut warnings = vec![];
+            if tile_width.min(tile_height) < min_tile_size {
+                Err(format!(
+                    "Tiles are {tile_width}x{tile_height} pixels but must be at least {min_tile_size} pixels wide and high"
+                ))?;
+            } else if tile_width.min(tile_height) < SMALL_TILE_SIZE {
+                warnings.push(format!(
+                    "tiles are only {tile_width}x{tile_height} pixels, check that the tile images are the right ones"
+                ));
+            }
+
+            let mut dense_propagater = vec![vec![vec![false; num_tiles]; num_tiles]; 4];
+            let mut propagator = vec![vec![vec![]; num_tiles]; 4];
+
+            if let Some(adjacency) = adjacency {
+                for (direction, dense) in Direction::ALL.into_iter().zip(&mut dense_propagater) {
+                    for (t1, allowed) in dense.iter_mut().enumerate() {
+                        for (t2, allowed) in allowed.iter_mut().enumerate() {
+                            *allowed = adjacency(t1, direction, t2)
+                                && adjacency(t2, direction.opposite(), t1);
+                        }
+                    }
+                }
+            } else {
+                let rules = neighbor_rules(
+                    &config.neighbors,
+                    &config.groups,
+                    &action,
+                    &first_occurence,
+                    &cardinalities,
+                    config.auto_symmetry_neighbors.unwrap_or(true),
+                )?;
+                for (index, earlier) in redundant_neighbors(&rules) {
+                    warnings.push(format!(
+                        "neighbors[{index}] {} is already implied by neighbors {earlier:?}",
+                        config.neighbors[index]
+                    ));
+                }
+                for (direction, t1, t2) in rules.into_iter().flatten() {
+                    dense_propagater[direction][t1][t2] = true;
+                }
 
-                dense_propagater[0][right][left] = true;
-                dense_propagater[0][action[right][6] as usize][action[left][6] as usize] = true;
-                dense_propagater[0][action[left][4] as usize][action[right][4] as usize] = true;
-                dense_propagater[0][action[left][2] as usize][action[right][2] as usize] = true;
+                #[allow(clippy::needless_range_loop)]
+                for t2 in 0..num_tiles {
+                    for t1 in 0..num_tiles {
+                        for direction in [Direction::Right, Direction::Up] {
+                            dense_propagater[direction.index()][t2][t1] =
+                                dense_propagater[direction.opposite().index()][t1][t2];
+                        }
+                    }
+                }
 
-                dense_propagater[1][up][down] = true;
-                dense_propagater[1][action[down][6] as usize][action[up][6] as usize] = true;
-                dense_propagater[1][action[up][4] as usize][action[down][4] as usize] = true;
-                dense_propagater[1][action[down][2] as usize][action[up][2] as usize] = true;
+                for dense in &mut dense_propagater {
+                    for t1 in 0..num_tiles {
+                        for t2 in 0..num_tiles {
+                            dense[t1][t2] = dense[source[t1]][source[t2]];
+                        }
+                    }
+                }
             }
 
-            for t2 in 0..num_tiles {
-                for t1 in 0..num_tiles {
-                    dense_propagater[2][t2][t1] = dense_propagater[0][t1][t2];
-                    dense_propagater[3][t2][t1] = dense_propagater[1][t1][t2];
+            if config.verify_edges {
+                // Recolored copies are compared as the tiles they copy
+                let edges: Vec<[Vec<Rgba<u8>>; 4]> =
+                    source.iter().map(|&t| tile_edges(&tiles[t])).collect();
+                let mut mismatched = 0;
+                for direction in Direction::ALL {
+                    let (d, opp) = (direction.index(), direction.opposite().index());
+                    for t1 in 0..num_tiles {
+                        for t2 in 0..num_tiles {
+                            if dense_propagater[d][t1][t2] && edges[t1][d] != edges[t2][opp] {
+                                dense_propagater[d][t1][t2] = false;
+                                mismatched += 1;
+                            }
+                        }
+                    }
+                }
+                if mismatched > 0 {
+                    // Every mismatch is found from both sides
+                    warnings.push(format!(
+                        "dropped {} adjacencies whose edges don't line up",
+                        mismatched / 2
+                    ));
                 }
             }
 
             let mut sparse_propagator: Vec<Vec<Vec<usize>>> = vec![vec![vec![]; num_tiles]; 4];
 
-            for (d, (sp, tp)) in sparse_propagator
-                .iter_mut()
-                .zip(dense_propagater)
-                .enumerate()
+            for (direction, (sp, tp)) in Direction::ALL
+                .into_iter()
+                .zip(sparse_propagator.iter_mut().zip(dense_propagater))
             {
                 for (t1, (sp, tp)) in sp.iter_mut().zip(tp).enumerate() {
                     for (t2, tp) in tp.iter().enumerate() {
@@ -290,27 +1343,139 @@ pub mod model {
                     }
 
                     if sp.is_empty() {
-                        eprintln!(
-                            "ERROR: tile {} has no neighbors in direction {}",
-                            tile_names[t1], d
-                        );
+                        warnings.push(format!(
+                            "tile {} has no neighbors in direction {:?}",
+                            tile_names[t1], direction
+                        ));
                     }
                     for (st, _) in sp.iter().enumerate() {
-                        propagator[d][t1].push(sp[st]);
+                        propagator[direction.index()][t1].push(sp[st]);
+                    }
+                }
+            }
+
+            // The names `[mapping]` and `[flags]` may list tile `t` of `base` under,
+            // least specific first
+            let lookup_names = |base: &BaseTile, t: usize| {
+                let mut names = vec![];
+                if let Some(source_base) = recolored_from.get(&base.name) {
+                    names.extend([source_base.clone(), tile_names[source[t]].clone()]);
+                }
+                names.extend([base.name.clone(), tile_names[t].clone()]);
+                names
+            };
+            let mapped_ids =
+                if config.mapping.is_empty() {
+                    None
+                } else {
+                    if let Some(name) = config.mapping.keys().find(|name| {
+                        !cardinalities.contains_key(*name) && !tile_names.contains(name)
+                    }) {
+                        Err(format!("[mapping] references unknown tile '{name}'"))?;
+                    }
+                    let mut ids = Vec::with_capacity(tile_names.len());
+                    for base in &base_tiles {
+                        for t in base.first..base.first + base.cardinality {
+                            let id = lookup_names(base, t)
+                                .into_iter()
+                                .rev()
+                                .find_map(|name| config.mapping.get(&name))
+                                .ok_or_else(|| {
+                                    format!("Tile '{}' is missing from [mapping]", base.name)
+                                })?;
+                            ids.push(id.clone());
+                        }
+                    }
+                    Some(ids)
+                };
+
+            if let Some(name) = config
+                .flags
+                .keys()
+                .find(|name| !cardinalities.contains_key(*name) && !tile_names.contains(name))
+            {
+                Err(format!("[flags] references unknown tile '{name}'"))?;
+            }
+            let mut tile_flags = Vec::with_capacity(tile_names.len());
+            for base in &base_tiles {
+                for t in base.first..base.first + base.cardinality {
+                    let mut flags = HashMap::new();
+                    for name in lookup_names(base, t) {
+                        flags.extend(config.flags.get(&name).cloned().unwrap_or_default());
                     }
+                    tile_flags.push(flags);
                 }
             }
 
-            let tile_size = tiles[0].image.width() as usize;
+            let tileset = Tileset {
+                tiles,
+                base_tiles,
+                tile_names,
+                mapped_ids,
+                tile_flags,
+                propagator,
+                action: action.iter().map(|a| a.map(|t| t as usize)).collect(),
+            };
+            let mut model = Self::from_tileset(tileset, width, height, periodic, heuristic);
+            model.warnings = warnings;
+            if !model.starting_entropy.is_finite() {
+                let suspects: Vec<&str> = model
+                    .base_tiles
+                    .iter()
+                    .filter(|base| {
+                        let weight = model.tiles[base.first].weight;
+                        !(weight.is_finite() && weight > 0.)
+                    })
+                    .map(|base| base.name.as_str())
+                    .collect();
+                Err(format!(
+                    "Tile weights give a non-finite starting entropy ({}), weights must be positive and finite{}",
+                    model.starting_entropy,
+                    if suspects.is_empty() {
+                        String::new()
+                    } else {
+                        format!(", check the weights of {}", suspects.join(", "))
+                    }
+                ))?;
+            }
+            Ok(model)
+        }
+
+        fn from_tileset(
+            tileset: Tileset,
+            width: usize,
+            height: usize,
+            periodic: bool,
+            heuristic: Heuristic,
+        ) -> Self {
+            let Tileset {
+                tiles,
+                base_tiles,
+                tile_names,
+                mapped_ids,
+                tile_flags,
+                propagator,
+                action,
+            } = tileset;
+            let num_tiles = tiles.len();
+            // Compiled tilesets are only written for models with tiles
+            let (tile_width, tile_height) = tiles.first().map_or((0, 0), |tile| {
+                (tile.image.width() as usize, tile.image.height() as usize)
+            });
             let sum_of_weights = tiles.iter().map(|t| t.weight).sum::<f64>();
-            let sum_of_weight_log_weights =
-                tiles.iter().map(|t| t.weight).map(|w| w * w.ln()).sum();
+            let weight_log_weights: Vec<f64> =
+                tiles.iter().map(|t| t.weight).map(|w| w * w.ln()).collect();
+            let sum_of_weight_log_weights = weight_log_weights.iter().sum();
             let starting_entropy = sum_of_weights.ln() - sum_of_weight_log_weights / sum_of_weights;
 
-            Ok(SimpleTiled {
+            let mut model = SimpleTiled {
                 tiles,
+                base_tiles,
                 tile_names,
-                tile_size,
+                mapped_ids,
+                tile_flags,
+                tile_width,
+                tile_height,
                 wave: vec![vec![true; num_tiles]; width * height],
                 propagator,
                 compatible: vec![vec![vec![0; 4]; num_tiles]; width * height],
@@ -321,7 +1486,7 @@ pub mod model {
                 height,
                 num_tiles,
                 n: 1,
-                weight_log_weights: vec![0.; num_tiles],
+                weight_log_weights,
                 distribution: vec![0.; num_tiles],
                 sums_of_ones: vec![0; width * height],
                 sum_of_weights,
@@ -332,14 +1497,47 @@ pub mod model {
                 entropies: vec![starting_entropy; width * height],
                 heuristic,
                 periodic,
-            })
+                stats: RunStats::default(),
+                background: [0; 4],
+                edge_blend: 0,
+                average_colors: OnceCell::new(),
+                constraints: vec![],
+                stack_warning: None,
+                warnings: vec![],
+                progress: false,
+                action,
+                output_symmetry: None,
+                edge_matching: None,
+                required_tiles: vec![],
+                recovery: Recovery::Restart,
+                adaptive_retries: false,
+                batch_observe: 1,
+                stop_entropy: None,
+                mask: None,
+                tile_caps: vec![],
+                guide: None,
+                weight_schedule: None,
+                tie_break: TieBreak::Random,
+                deterministic: false,
+                frontier_only: false,
+                frontier: vec![false; width * height],
+                failure_memory: HashMap::new(),
+                opening: Vec::with_capacity(OPENING_OBSERVATIONS),
+                contradiction: None,
+                tie_breaks: vec![0.; width * height],
+                record_events: false,
+                events: vec![],
+            };
+            model.clear();
+            model
         }
         fn clear(&mut self) {
             for i in 0..self.wave.len() {
                 for t in 0..self.num_tiles {
                     self.wave[i][t] = true;
-                    for (d, opp) in OPPOSITE.iter().enumerate() {
-                        self.compatible[i][t][d] = self.propagator[*opp][t].len() as isize;
+                    for d in Direction::ALL {
+                        self.compatible[i][t][d.index()] =
+                            self.propagator[d.opposite().index()][t].len() as isize;
                     }
                 }
                 self.sums_of_ones[i] = self.tiles.len() as isize;
@@ -348,15 +1546,33 @@ pub mod model {
                 self.entropies[i] = self.starting_entropy;
                 self.observed[i] = None;
             }
+            self.stack.clear();
+            self.contradiction = None;
+            self.frontier.fill(false);
+            self.opening.clear();
+            self.events.clear();
             self.observed_so_far = 0;
+            self.stats = RunStats::default();
+        }
+        /// Whether an `n`×`n` pattern anchored at its top-left cell `(x, y)` lies
+        /// inside the grid.
+        ///
+        /// The pattern covers columns `x..x + n` and rows `y..y + n`, so the last
+        /// anchor that fits is `(width - n, height - n)` and anything with
+        /// `x + n > width` or `y + n > height` is out of bounds. Periodic grids wrap
+        /// around, so every anchor fits.
+        fn pattern_fits(&self, x: isize, y: isize) -> bool {
+            self.periodic
+                || (x >= 0
+                    && y >= 0
+                    && x as usize + self.n <= self.width
+                    && y as usize + self.n <= self.height)
         }
-        fn next_unobserved_node(&mut self, rng: &mut ChaCha8Rng) -> Option<usize> {
+
+        fn next_unobserved_node(&mut self) -> Option<usize> {
             if self.heuristic == Heuristic::ScanLine {
                 for i in self.observed_so_far..self.wave.len() {
-                    if !self.periodic
-                        && (i % self.width + self.n > self.width
-                            || i / self.width + self.n > self.height)
-                    {
+                    if !self.cell_fits(i) {
                         continue;
                     }
                     if self.sums_of_ones[i] > 1 {
@@ -366,216 +1582,5996 @@ pub mod model {
                 }
                 None
             } else {
+                let frontier_only = self.frontier_active();
+                let candidates = (0..self.wave.len()).filter(|&i| {
+                    // Collapsed and contradicted cells have no meaningful entropy
+                    self.cell_fits(i)
+                        && (!frontier_only || self.frontier[i])
+                        && self.sums_of_ones[i] > 1
+                });
                 let mut min = 10_000.;
                 let mut argmin = None;
-                for (i, remaining_values) in self.sums_of_ones.iter().enumerate() {
-                    if !self.periodic
-                        && (i % self.width + self.n > self.width
-                            || i / self.width + self.n > self.height)
-                    {
-                        continue;
-                    }
-                    let entropy = if self.heuristic == Heuristic::Entropy {
-                        self.entropies[i]
-                    } else {
-                        *remaining_values as f64
-                    };
-                    if *remaining_values > 1 && entropy <= min {
-                        let noise = 0.000_001 * rng.gen::<f64>();
-                        if entropy + noise < min {
-                            min = entropy + noise;
+                if self.heuristic == Heuristic::Agreement {
+                    let lowest = candidates
+                        .clone()
+                        .map(|i| self.selection_entropy(i))
+                        .fold(f64::INFINITY, f64::min);
+                    // The tie breaks stay below the agreement bonus of one neighbor
+                    for i in candidates {
+                        if self.selection_entropy(i) > lowest + AGREEMENT_TOLERANCE {
+                            continue;
+                        }
+                        let score = self.tie_breaks[i] - self.collapsed_neighbors(i) as f64;
+                        if score < min {
+                            min = score;
                             argmin = Some(i);
                         }
                     }
+                    return argmin;
+                }
+                if self.heuristic == Heuristic::MRV && self.tie_break != TieBreak::Random {
+                    let lowest = candidates.clone().map(|i| self.sums_of_ones[i]).min()?;
+                    // Position scores differ by at least 0.25, far above the random tie breaks
+                    return candidates
+                        .filter(|&i| self.sums_of_ones[i] == lowest)
+                        .map(|i| (self.position_score(i) + self.tie_breaks[i], i))
+                        .min_by(|a, b| a.0.total_cmp(&b.0))
+                        .map(|(_, i)| i);
+                }
+                for i in candidates {
+                    let entropy = self.selection_entropy(i);
+                    if entropy + self.tie_breaks[i] < min {
+                        min = entropy + self.tie_breaks[i];
+                        argmin = Some(i);
+                    }
                 }
                 argmin
             }
         }
+
+        /// The entropy cells are picked by, the number of possible tiles for MRV.
+        fn selection_entropy(&self, i: usize) -> f64 {
+            if self.heuristic == Heuristic::MRV {
+                self.sums_of_ones[i] as f64
+            } else {
+                self.entropies[i]
+            }
+        }
+
+        /// Lower for the cells [`TieBreak`] prefers.
+        fn position_score(&self, i: usize) -> f64 {
+            match self.tie_break {
+                TieBreak::Random => 0.,
+                TieBreak::Center => {
+                    let (x, y) = self.coords(i);
+                    let dx = x as f64 + 0.5 - self.width as f64 / 2.;
+                    let dy = y as f64 + 0.5 - self.height as f64 / 2.;
+                    dx * dx + dy * dy
+                }
+                TieBreak::Observed => -(self.collapsed_neighbors(i) as f64),
+            }
+        }
+
+        /// How many of the cells next to `i` have collapsed to a single tile.
+        fn collapsed_neighbors(&self, i: usize) -> usize {
+            self.neighbors(i)
+                .filter(|&(_, neighbor)| self.sums_of_ones[neighbor] == 1)
+                .count()
+        }
+        /// Whether observations are limited to the frontier, which only starts once
+        /// anything has collapsed.
+        fn frontier_active(&self) -> bool {
+            self.frontier_only
+                && self
+                    .frontier
+                    .iter()
+                    .zip(&self.sums_of_ones)
+                    .any(|(&frontier, &remaining)| frontier && remaining > 1)
+        }
+
+        /// Picks up to `count` more cells to observe along with `first` before
+        /// propagating, lowest entropy first and none next to another.
+        fn batch_companions(&self, first: usize, count: usize) -> Vec<usize> {
+            if count == 0 || self.heuristic == Heuristic::ScanLine {
+                return vec![];
+            }
+            let frontier_only = self.frontier_active();
+            let mut candidates: Vec<(f64, usize)> = (0..self.wave.len())
+                .filter(|&i| {
+                    self.sums_of_ones[i] > 1
+                        && (!frontier_only || self.frontier[i])
+                        && self.cell_fits(i)
+                })
+                .map(|i| (self.selection_entropy(i) + self.tie_breaks[i], i))
+                .collect();
+            candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut batch = vec![first];
+            for (_, i) in candidates {
+                if batch.len() > count {
+                    break;
+                }
+                let adjacent = self.neighbors(i).any(|(_, n)| batch.contains(&n));
+                if !adjacent {
+                    batch.push(i);
+                }
+            }
+            batch.split_off(1)
+        }
+
         fn observe(&mut self, node: usize, rng: &mut ChaCha8Rng) {
             let w = &self.wave[node];
-            for ((distribution, w), weight) in self
+            let opening = self.adaptive_retries && self.stats.observations < OPENING_OBSERVATIONS;
+            let progress = self.weight_schedule.as_ref().map(|schedule| {
+                let collapsed = self.sums_of_ones.iter().filter(|&&ones| ones == 1).count();
+                (schedule, collapsed as f64 / self.wave.len() as f64)
+            });
+            for (t, ((distribution, w), weight)) in self
                 .distribution
                 .iter_mut()
                 .zip(w)
                 .zip(self.tiles.iter().map(|t| t.weight))
+                .enumerate()
             {
                 *distribution = if *w { weight } else { 0.0 };
+                if let Some((WeightSchedule(schedule), fraction)) = progress {
+                    *distribution *= schedule(t, fraction);
+                }
+                if let Some(guide) = &self.guide {
+                    *distribution *= guide[node][t];
+                }
+                if opening {
+                    if let Some(failures) = self.failure_memory.get(&(node, t)) {
+                        *distribution /= 1. + *failures as f64;
+                    }
+                }
+            }
+            let r = if self.deterministic {
+                (0..self.num_tiles).fold(0, |heaviest, t| {
+                    if self.distribution[t] > self.distribution[heaviest] {
+                        t
+                    } else {
+                        heaviest
+                    }
+                })
+            } else {
+                random_from_distr(&self.distribution, unit_f64(rng))
+            };
+            if self.record_events {
+                self.events.push(SolveEvent::Observe {
+                    cell: node,
+                    tile: r,
+                });
             }
-            let r = random_from_distr(&self.distribution, rng.gen());
             for t in 0..self.num_tiles {
                 if self.wave[node][t] != (t == r) {
                     self.ban(node, t);
                 }
             }
         }
+        /// Copies the tile just observed at `node` to the cells mirroring it under
+        /// the output symmetry, reflected to match.
+        ///
+        /// Returns `false` if a mirrored cell can't hold the reflected tile.
+        fn mirror_observation(&mut self, node: usize) -> bool {
+            let Some(symmetry) = self.output_symmetry else {
+                return true;
+            };
+            let Some(tile) = self.wave[node].iter().position(|&possible| possible) else {
+                return false;
+            };
+            let (x, y) = self.coords(node);
+            let mirrored_x = self.width - 1 - x;
+            let mirrored_y = self.height - 1 - y;
+            // Columns 4, 6 and 2 of `action` are the horizontal flip, the vertical
+            // flip and the half turn
+            let mirrors = match symmetry {
+                OutputSymmetry::LeftRight => vec![(mirrored_x, y, 4)],
+                OutputSymmetry::TopBottom => vec![(x, mirrored_y, 6)],
+                OutputSymmetry::FourFold => vec![
+                    (mirrored_x, y, 4),
+                    (x, mirrored_y, 6),
+                    (mirrored_x, mirrored_y, 2),
+                ],
+            };
+            for (mx, my, transform) in mirrors {
+                let mirror = self.index(mx, my);
+                let mirrored_tile = self.action[tile][transform];
+                if !self.wave[mirror][mirrored_tile] {
+                    return false;
+                }
+                for t in 0..self.num_tiles {
+                    if t != mirrored_tile && self.wave[mirror][t] {
+                        self.ban(mirror, t);
+                    }
+                }
+            }
+            true
+        }
+
         fn ban(&mut self, i: usize, t: usize) {
             self.wave[i][t] = false;
+            if self.record_events {
+                self.events.push(SolveEvent::Ban { cell: i, tile: t });
+            }
 
             let comp = &mut self.compatible[i][t];
             for c in comp {
                 *c = 0;
             }
             self.stack.push((i, t));
+            self.stats.peak_stack_depth = self.stats.peak_stack_depth.max(self.stack.len());
 
             self.sums_of_ones[i] -= 1;
+            if self.sums_of_ones[i] == 0 && self.contradiction.is_none() {
+                self.contradiction = Some(i);
+            }
+            if self.sums_of_ones[i] == 1 && self.frontier_only {
+                for direction in Direction::ALL {
+                    if let Some(neighbor) = self.neighbor(i, direction) {
+                        self.frontier[neighbor] = true;
+                    }
+                }
+            }
+            for partner in self.edge_partners(i).into_iter().flatten() {
+                if self.wave[partner][t] {
+                    self.ban(partner, t);
+                }
+            }
             self.sums_of_weights[i] -= self.tiles[t].weight;
             self.sums_of_weight_log_weights[i] -= self.weight_log_weights[t];
 
             let sum = self.sums_of_weights[i];
-            self.entropies[i] = sum.ln() - self.sums_of_weight_log_weights[i] / sum;
+            let entropy = sum.ln() - self.sums_of_weight_log_weights[i] / sum;
+            // Once the last tile is banned `sum` reaches (roughly) zero, which would
+            // leave NaN or infinities behind
+            self.entropies[i] = if self.sums_of_ones[i] > 0 && sum > 0. && entropy.is_finite() {
+                entropy
+            } else {
+                0.
+            };
         }
+        /// Spreads the bans on the stack to the neighboring cells, stopping at the
+        /// first cell left without a possible tile.
         fn propagate(&mut self) -> bool {
-            while let Some((i1, t1)) = self.stack.pop() {
-                let x1 = i1 % self.width;
-                let y1 = i1 / self.width;
+            while self.contradiction.is_none() {
+                let Some((i1, t1)) = self.stack.pop() else {
+                    break;
+                };
 
-                for d in 0..4 {
-                    let width = self.width as isize;
-                    let height = self.height as isize;
-                    let mut x2 = x1 as isize + DX[d];
-                    let mut y2 = y1 as isize + DY[d];
-
-                    if !self.periodic
-                        && (x2 < 0
-                            || y2 < 0
-                            || x2 as usize + self.n > self.width
-                            || y2 as usize + self.n > self.height)
-                    {
+                for direction in Direction::ALL {
+                    let d = direction.index();
+                    let Some(i2) = self.neighbor(i1, direction) else {
+                        continue;
+                    };
+                    if self.masked(i2) {
                         continue;
                     }
 
-                    if x2 < 0 {
-                        x2 += width;
-                    } else if x2 >= width {
-                        x2 -= width;
-                    }
-                    if y2 < 0 {
-                        y2 += height;
-                    } else if y2 >= height {
-                        y2 -= height;
-                    }
-
-                    let i2 = x2 + y2 * width;
-
                     let mut ban_list = vec![];
                     for t2 in &self.propagator[d][t1] {
-                        self.compatible[i2 as usize][*t2][d] -= 1;
-                        if self.compatible[i2 as usize][*t2][d] == 0 {
+                        self.compatible[i2][*t2][d] -= 1;
+                        if self.compatible[i2][*t2][d] == 0 {
                             ban_list.push(*t2);
                         }
                     }
 
                     for t2 in ban_list {
-                        if t2 == 0 {
-                            //println!("Banning 0");
+                        self.ban(i2, t2);
+                    }
+                }
+            }
+            self.contradiction.is_none()
+        }
+
+        /// The cells on the opposite edges that `i` has to match under the edge
+        /// matching, horizontally and then vertically.
+        fn edge_partners(&self, i: usize) -> [Option<usize>; 2] {
+            let Some(matching) = self.edge_matching else {
+                return [None; 2];
+            };
+            let (x, y) = self.coords(i);
+            let horizontal = match matching {
+                EdgeMatching::Vertical => None,
+                _ if x == 0 => Some(self.index(self.width - 1, y)),
+                _ if x == self.width - 1 => Some(self.index(0, y)),
+                _ => None,
+            };
+            let vertical = match matching {
+                EdgeMatching::Horizontal => None,
+                _ if y == 0 => Some(self.index(x, self.height - 1)),
+                _ if y == self.height - 1 => Some(self.index(x, 0)),
+                _ => None,
+            };
+            [horizontal, vertical]
+        }
+
+        /// The cells next to `cell` with the direction each lies in, wrapping
+        /// around periodic grids and leaving out sides beyond the edge otherwise.
+        pub fn neighbors(&self, cell: usize) -> impl Iterator<Item = (Direction, usize)> + '_ {
+            Direction::ALL.into_iter().filter_map(move |direction| {
+                self.neighbor(cell, direction)
+                    .map(|neighbor| (direction, neighbor))
+            })
+        }
+
+        /// The cell next to `i` in `direction`, wrapping around periodic grids.
+        fn neighbor(&self, i: usize, direction: Direction) -> Option<usize> {
+            let width = self.width as isize;
+            let height = self.height as isize;
+            let (dx, dy) = direction.delta();
+            let (x, y) = self.coords(i);
+            let (x, y) = (x as isize + dx, y as isize + dy);
+            if !self.pattern_fits(x, y) {
+                return None;
+            }
+            Some(self.index(x.rem_euclid(width) as usize, y.rem_euclid(height) as usize))
+        }
+
+        /// The flat index of the cell at `(x, y)`, row by row from the top left.
+        fn index(&self, x: usize, y: usize) -> usize {
+            x + y * self.width
+        }
+
+        /// The `(x, y)` of the cell at flat index `i`, see [`SimpleTiled::index`].
+        fn coords(&self, i: usize) -> (usize, usize) {
+            (i % self.width, i / self.width)
+        }
+
+        /// Whether a pattern anchored at cell `i` lies inside the grid, see
+        /// [`SimpleTiled::pattern_fits`], and the mask doesn't leave it out.
+        fn cell_fits(&self, i: usize) -> bool {
+            let (x, y) = self.coords(i);
+            self.pattern_fits(x as isize, y as isize) && !self.masked(i)
+        }
+
+        /// The anchor cell whose pattern covers cell `(x, y)`, with the offset of
+        /// `(x, y)` in that pattern. Every cell is its own anchor on periodic
+        /// grids, otherwise the last `n - 1` rows and columns belong to the last
+        /// anchor before them, see [`SimpleTiled::pattern_fits`].
+        fn pattern_anchor(&self, x: usize, y: usize) -> (usize, usize, usize) {
+            let anchor = |at: usize, size: usize| {
+                if self.periodic || at + self.n <= size {
+                    (at, 0)
+                } else {
+                    (size - self.n, at + self.n - size)
+                }
+            };
+            let (ax, dx) = anchor(x, self.width);
+            let (ay, dy) = anchor(y, self.height);
+            (self.index(ax, ay), dx, dy)
+        }
+
+        /// Whether [`SimpleTiled::set_mask`] leaves cell `i` ungenerated.
+        fn masked(&self, i: usize) -> bool {
+            self.mask.as_ref().is_some_and(|mask| !mask[i])
+        }
+
+        /// How many generated cells are still unobserved.
+        fn unobserved(&self) -> usize {
+            (0..self.observed.len())
+                .filter(|&i| self.observed[i].is_none() && self.cell_fits(i))
+                .count()
+        }
+
+        /// Recovers from the contradiction that stopped propagation by letting the
+        /// contradicted cell and its direct neighbors hold any tile again, then
+        /// propagating into them from the rest of the grid. Repeats while that
+        /// leads to new contradictions, up to as many resets per run as there are
+        /// cells.
+        ///
+        /// Unlike backtracking, nothing outside the reset cells is undone. A
+        /// contradiction caused by bans further away just comes back until the
+        /// budget runs out, and the run fails. Reset cells keep the constraints
+        /// from [`SimpleTiled::constrain_edge_from`] but lose required tile pins and
+        /// mirrored observations, so those are no longer guaranteed.
+        fn resolve_local_contradiction(&mut self) -> bool {
+            while let Some(contradicted) = self.contradiction {
+                if self.stats.local_resets >= self.wave.len() {
+                    return false;
+                }
+                self.stats.local_resets += 1;
+                let mut cluster: Vec<usize> = self
+                    .neighbors(contradicted)
+                    .map(|(_, n)| n)
+                    .chain([contradicted])
+                    .collect();
+                cluster.sort_unstable();
+                cluster.dedup();
+                self.reset_cells(&cluster);
+                self.propagate();
+            }
+            true
+        }
+
+        /// Makes every tile possible again at the `cluster` cells and recounts the
+        /// supports around them, then bans what the surrounding cells rule out.
+        fn reset_cells(&mut self, cluster: &[usize]) {
+            if self.record_events {
+                self.events.push(SolveEvent::Reset {
+                    cells: cluster.to_vec(),
+                });
+            }
+            let mut in_cluster = vec![false; self.wave.len()];
+            for &i in cluster {
+                in_cluster[i] = true;
+            }
+            // Pending bans of the reset cells are void, the others still have to
+            // reach their neighbors and so count as possible when recounting
+            self.stack.retain(|&(i, _)| !in_cluster[i]);
+            let pending: HashSet<(usize, usize)> = self.stack.iter().copied().collect();
+            self.contradiction = None;
+
+            for &i in cluster {
+                self.wave[i].fill(true);
+                self.sums_of_ones[i] = self.num_tiles as isize;
+                self.sums_of_weights[i] = self.sum_of_weights;
+                self.sums_of_weight_log_weights[i] = self.sum_of_weight_log_weights;
+                self.entropies[i] = self.starting_entropy;
+                self.observed[i] = None;
+            }
+            self.observed_so_far = self.observed_so_far.min(cluster[0]);
+
+            let mut unsupported = vec![];
+            for &i in cluster {
+                for direction in Direction::ALL {
+                    let d = direction.index();
+                    let opp = direction.opposite().index();
+                    let source = self.neighbor(i, direction.opposite());
+                    for t in 0..self.num_tiles {
+                        let supports = &self.propagator[opp][t];
+                        self.compatible[i][t][d] = match source {
+                            Some(source) => supports
+                                .iter()
+                                .filter(|&&s| {
+                                    self.wave[source][s] || pending.contains(&(source, s))
+                                })
+                                .count() as isize,
+                            None => supports.len() as isize,
+                        };
+                        if self.compatible[i][t][d] == 0 && !supports.is_empty() {
+                            unsupported.push((i, t));
+                        }
+                    }
+
+                    // Cells next to the cluster now see every tile on this side
+                    if let Some(target) = self.neighbor(i, direction).filter(|&n| !in_cluster[n]) {
+                        for t in 0..self.num_tiles {
+                            if self.wave[target][t] {
+                                self.compatible[target][t][d] =
+                                    self.propagator[opp][t].len() as isize;
+                            }
                         }
-                        self.ban(i2 as usize, t2);
                     }
                 }
             }
-            self.sums_of_ones[0] > 0
+
+            for (i, t) in unsupported {
+                if self.wave[i][t] {
+                    self.ban(i, t);
+                }
+            }
+            let constraints = std::mem::take(&mut self.constraints);
+            for (i, allowed) in &constraints {
+                if in_cluster[*i] {
+                    self.restrict(*i, allowed);
+                }
+            }
+            self.constraints = constraints;
+            for &i in cluster {
+                for partner in self.edge_partners(i).into_iter().flatten() {
+                    let allowed = self.wave[partner].clone();
+                    self.restrict(i, &allowed);
+                }
+            }
         }
-    }
 
-    impl Model for SimpleTiled {
-        fn run(&mut self, seed: u64, limit: usize) -> bool {
-            println!("Ran this model");
-            self.clear();
-            let mut rng = ChaCha8Rng::seed_from_u64(seed);
-            let bar = ProgressBar::new(self.observed.len() as u64);
-            bar.set_style(
-                ProgressStyle::with_template(
-                    "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] ({eta:>3}) [{pos:>7}/{len:7}] {msg}",
-                )
-                .unwrap(),
-            );
+        /// Bans every tile at cell `i` that `allowed` doesn't include.
+        fn restrict(&mut self, i: usize, allowed: &[bool]) {
+            for (t, &allowed) in allowed.iter().enumerate() {
+                if self.wave[i][t] && !allowed {
+                    self.ban(i, t);
+                }
+            }
+        }
+
+        fn apply_constraints(&mut self) -> bool {
+            let constraints = std::mem::take(&mut self.constraints);
+            for (i, allowed) in &constraints {
+                self.restrict(*i, allowed);
+            }
+            self.constraints = constraints;
+            self.propagate() && self.sums_of_ones.iter().all(|&remaining| remaining > 0)
+        }
 
-            for _ in 0..limit {
-                if let Some(node) = self.next_unobserved_node(&mut rng) {
-                    //println!("Found a node");
-                    bar.inc(1);
-                    self.observe(node, &mut rng);
-                    let success = self.propagate();
+        /// Pins each required tile to a random cell that can still hold it, trying
+        /// other cells whenever the placement immediately causes a contradiction.
+        ///
+        /// Returns the name of the first required tile that fits nowhere.
+        fn place_required_tiles(&mut self, rng: &mut ChaCha8Rng) -> Result<(), String> {
+            let required = std::mem::take(&mut self.required_tiles);
+            let mut result = Ok(());
+            for (name, allowed) in &required {
+                let mut candidates: Vec<usize> = (0..self.wave.len())
+                    .filter(|&i| {
+                        self.cell_fits(i)
+                            && (0..self.num_tiles).any(|t| self.wave[i][t] && allowed[t])
+                    })
+                    .collect();
+                candidates.shuffle(rng);
+
+                let before = self.snapshot();
+                let placed = candidates.into_iter().any(|i| {
+                    self.restrict(i, allowed);
+                    let success = self.propagate()
+                        && self.sums_of_ones.iter().all(|&remaining| remaining > 0);
                     if !success {
+                        self.restore(&before);
+                    }
+                    success
+                });
+                if !placed {
+                    result = Err(name.clone());
+                    break;
+                }
+            }
+            self.required_tiles = required;
+            result
+        }
+
+        fn run_with_progress(&mut self, seed: u64, limit: usize, bar: &ProgressBar) -> bool {
+            let mut rng = match self.init(seed) {
+                Ok(rng) => rng,
+                Err(err) => {
+                    bar.abandon_with_message(err.to_string());
+                    return false;
+                }
+            };
+            while self.stats.observations < limit {
+                let before = self.stats.observations;
+                let result = self.advance(&mut rng, limit - before);
+                bar.inc((self.stats.observations - before) as u64);
+                match result {
+                    StepResult::Progressed => (),
+                    StepResult::Finished => {
+                        bar.finish_with_message("Done");
+                        return true;
+                    }
+                    StepResult::Contradiction => {
                         bar.abandon_with_message("Propagation failed");
                         return false;
                     }
-                } else {
-                    //println!("Ran out of nodes");
-                    bar.finish_with_message("Done");
-                    for i in 0..self.wave.len() {
-                        for t in 0..self.wave[i].len() {
-                            if self.wave[i][t] {
-                                self.observed[i] = Some(t);
-                                break;
-                            }
-                        }
-                    }
-                    //println!("Observed: {:?}", self.observed);
-                    return !self.observed.iter().any(Option::is_none);
                 }
             }
             true
         }
 
-        fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
-            if self.observed.iter().any(Option::is_none) {
-                return Err("Model is not fully rendered")?;
+        /// Clears the model and applies its constraints and required tiles, then
+        /// returns the generator seeded with `seed` for driving the run with
+        /// [`SimpleTiled::step`].
+        ///
+        /// Errors if the constraints or required tiles contradict each other.
+        pub fn init(&mut self, seed: u64) -> Result<ChaCha8Rng, Box<dyn Error>> {
+            self.clear();
+            if !self.apply_constraints() {
+                self.stats.contradictions += 1;
+                return Err("Constraints are contradictory")?;
             }
-            let mut imgbuf = ImageBuffer::new(
-                (self.width * self.tile_size) as u32,
-                (self.height * self.tile_size) as u32,
-            );
-            for y in 0..self.height {
-                for x in 0..self.width {
-                    imgbuf.copy_from(
-                        &self.tiles[self.observed[x + y * self.width].unwrap()].image,
-                        (x * self.tile_size) as u32,
-                        (y * self.tile_size) as u32,
-                    )?;
-                }
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            for tie_break in &mut self.tie_breaks {
+                *tie_break = 0.000_001 * unit_f64(&mut rng);
             }
-            imgbuf.save(path)?;
-            Ok(())
+            if let Err(name) = self.place_required_tiles(&mut rng) {
+                self.stats.contradictions += 1;
+                self.stats.unplaced_tile = Some(name.clone());
+                return Err(format!("Required tile '{name}' fits nowhere"))?;
+            }
+            Ok(rng)
         }
-    }
 
-    impl Display for SimpleTiled {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            let count = self
-                .observed
-                .iter()
-                .fold(0, |acc, obs| if obs.is_none() { acc + 1 } else { acc });
-            if count > 0 {
-                write!(f, "{count} unobserved tiles")?;
+        /// Clears every observation and ban, leaving the settings alone.
+        pub fn reset(&mut self) {
+            self.clear();
+        }
+
+        /// Observes the next cell and propagates, like one iteration of
+        /// [`Model::run`], with `rng` from [`SimpleTiled::init`].
+        ///
+        /// Once no cell is left to observe, every cell takes its only possible
+        /// tile and the model reports [`StepResult::Finished`].
+        pub fn step(&mut self, rng: &mut ChaCha8Rng) -> StepResult {
+            self.advance(rng, usize::MAX)
+        }
+
+        /// One step observing at most `left` cells, see [`SimpleTiled::step`].
+        fn advance(&mut self, rng: &mut ChaCha8Rng, left: usize) -> StepResult {
+            let node = if self.entropy_settled() {
+                None
             } else {
-                for y in 0..self.height {
-                    for x in 0..self.width {
-                        write!(
-                            f,
-                            "{},\t",
-                            self.tile_names[self.observed[x + y * self.width].unwrap()]
-                        )?;
+                self.next_unobserved_node()
+            };
+            let Some(node) = node else {
+                if !self.observe_remaining() {
+                    return StepResult::Contradiction;
+                }
+                self.failure_memory.clear();
+                return StepResult::Finished;
+            };
+            let companions =
+                self.batch_companions(node, self.batch_observe.min(left).saturating_sub(1));
+            let mut mirrored = true;
+            for node in std::iter::once(node).chain(companions) {
+                self.observe(node, rng);
+                if self.opening.len() < OPENING_OBSERVATIONS {
+                    if let Some(t) = self.wave[node].iter().position(|&possible| possible) {
+                        self.opening.push((node, t));
                     }
-                    writeln!(f)?;
                 }
+                self.stats.observations += 1;
+                mirrored = mirrored && self.mirror_observation(node);
             }
-            Ok(())
+            let success = mirrored
+                && self.propagate_or_recover()
+                && (self.tile_caps.is_empty() || self.enforce_tile_caps());
+            if !success {
+                self.stats.contradictions += 1;
+                self.stats.contradiction = self.contradiction.map(|i| self.coords(i));
+                if self.adaptive_retries {
+                    for observation in std::mem::take(&mut self.opening) {
+                        *self.failure_memory.entry(observation).or_default() += 1;
+                    }
+                }
+                return StepResult::Contradiction;
+            }
+            StepResult::Progressed
         }
-    }
-}
 
-fn random_from_distr(weights: &[f64], r: f64) -> usize {
-    let sum = weights.iter().fold(0., |acc, w| acc + w);
-    let threshold = r * sum;
-    let mut partial_sum = 0.;
-    for (i, weight) in weights.iter().enumerate() {
-        partial_sum += weight;
-        if partial_sum >= threshold {
-            return i;
+        /// Propagates, falling back to [`Recovery::ResetCluster`] if that is set.
+        fn propagate_or_recover(&mut self) -> bool {
+            self.propagate()
+                || (self.recovery == Recovery::ResetCluster && self.resolve_local_contradiction())
         }
-    }
-    0
+
+        /// Bans every tile that reached its cap from the uncollapsed cells, see
+        /// [`SimpleTiled::set_tile_caps`].
+        fn enforce_tile_caps(&mut self) -> bool {
+            let histogram = self.tile_histogram();
+            let generated = self.unmasked_cells();
+            let caps = std::mem::take(&mut self.tile_caps);
+            for (tiles, fraction) in &caps {
+                let count: usize = (0..self.num_tiles)
+                    .filter(|&t| tiles[t])
+                    .map(|t| histogram[t])
+                    .sum();
+                if (count as f64) < fraction * generated as f64 {
+                    continue;
+                }
+                for i in 0..self.wave.len() {
+                    if self.sums_of_ones[i] > 1 && !self.masked(i) {
+                        for (t, &capped) in tiles.iter().enumerate() {
+                            if capped && self.wave[i][t] {
+                                self.ban(i, t);
+                            }
+                        }
+                    }
+                }
+            }
+            self.tile_caps = caps;
+            self.propagate_or_recover()
+        }
+
+        /// How many cells the mask leaves to generate.
+        fn unmasked_cells(&self) -> usize {
+            (0..self.wave.len()).filter(|&i| !self.masked(i)).count()
+        }
+
+        /// Sets every generated cell to its first possible tile, returning whether
+        /// each of them had one.
+        fn observe_remaining(&mut self) -> bool {
+            for i in 0..self.wave.len() {
+                self.observed[i] = if !self.cell_fits(i) {
+                    None
+                } else {
+                    self.wave[i].iter().position(|&possible| possible)
+                };
+            }
+            self.unobserved() == 0
+        }
+
+        /// Whether the mean entropy of the cells with more than one possible tile
+        /// has dropped below the threshold of [`SimpleTiled::set_stop_entropy`].
+        fn entropy_settled(&self) -> bool {
+            let Some(threshold) = self.stop_entropy else {
+                return false;
+            };
+            let (sum, count) = (0..self.wave.len())
+                .filter(|&i| self.sums_of_ones[i] > 1 && !self.masked(i))
+                .fold((0., 0), |(sum, count), i| {
+                    (sum + self.entropies[i], count + 1)
+                });
+            count > 0 && sum / (count as f64) < threshold
+        }
+
+        /// Runs the model with `seed` like [`Model::run`] but without any output,
+        /// and clears it again afterwards so seeds can be scanned back to back.
+        pub fn try_seed(&mut self, seed: u64, limit: usize) -> bool {
+            let success = self.run_with_progress(seed, limit, &ProgressBar::hidden());
+            self.clear();
+            success
+        }
+
+        /// Solves a grid `factor` times smaller in each direction first as a quick
+        /// preview and hands it to `preview`, then runs the full grid with every
+        /// `factor`th cell in both directions fixed to the preview's tile there.
+        ///
+        /// Returns `false` if either grid fails. Fixing cells far apart can leave
+        /// the full grid without a solution, so retry with another seed then.
+        pub fn run_progressive(
+            &mut self,
+            seed: u64,
+            factor: usize,
+            mut preview: impl FnMut(&SimpleTiled),
+        ) -> bool {
+            let factor = factor.max(1);
+            let mut coarse = Self::from_tileset(
+                self.tileset(),
+                self.width.div_ceil(factor),
+                self.height.div_ceil(factor),
+                self.periodic,
+                self.heuristic.clone(),
+            );
+            if !coarse.run_with_progress(seed, usize::MAX, &ProgressBar::hidden()) {
+                return false;
+            }
+            preview(&coarse);
+
+            let constraints = self.constraints.clone();
+            for (i, t) in coarse.observed.iter().enumerate() {
+                let x = i % coarse.width * factor;
+                let y = i / coarse.width * factor;
+                let mut allowed = vec![false; self.num_tiles];
+                allowed[t.unwrap()] = true;
+                self.constraints.push((self.index(x, y), allowed));
+            }
+            let success = self.run(derive_seed(seed, 1), usize::MAX);
+            self.constraints = constraints;
+            success
+        }
+
+        /// Like [`Model::run`], but a contradiction undoes the latest observation
+        /// and bans the tile it picked instead of failing, going further back
+        /// whenever that contradicts too. Fails once `max_backtracks` observations
+        /// have been undone or every choice has been tried.
+        ///
+        /// Every observation keeps a [`ModelSnapshot`] to go back to, so memory
+        /// grows with the number of cells times the grid size. Batch observation
+        /// doesn't apply.
+        pub fn run_with_backtracking(&mut self, seed: u64, max_backtracks: usize) -> bool {
+            let Ok(mut rng) = self.init(seed) else {
+                return false;
+            };
+
+            let mut choices: Vec<(ModelSnapshot, usize, usize)> = vec![];
+            loop {
+                let node = if self.entropy_settled() {
+                    None
+                } else {
+                    self.next_unobserved_node()
+                };
+                let Some(node) = node else {
+                    return self.observe_remaining();
+                };
+                let before = self.snapshot();
+                self.observe(node, &mut rng);
+                self.stats.observations += 1;
+                let Some(t) = self.wave[node].iter().position(|&possible| possible) else {
+                    return false;
+                };
+                choices.push((before, node, t));
+                let mut success = self.mirror_observation(node)
+                    && self.propagate()
+                    && (self.tile_caps.is_empty() || self.enforce_tile_caps());
+                while !success {
+                    self.stats.contradictions += 1;
+                    let contradiction = self.contradiction.map(|i| self.coords(i));
+                    let Some((before, node, t)) = choices.pop() else {
+                        self.stats.contradiction = contradiction;
+                        return false;
+                    };
+                    if self.stats.backtracks == max_backtracks {
+                        self.stats.contradiction = contradiction;
+                        return false;
+                    }
+                    self.stats.backtracks += 1;
+                    self.restore(&before);
+                    self.ban(node, t);
+                    success =
+                        self.propagate() && (self.tile_caps.is_empty() || self.enforce_tile_caps());
+                }
+            }
+        }
+
+        /// Solves a grid `margin` cells larger on every side and keeps its middle,
+        /// for when the requested size keeps failing. Outside the grid edges no
+        /// tile has to fit, so the extra cells give the solver more room.
+        ///
+        /// The crop can cut through features that would have ended at the edge
+        /// of the requested size. Constraints, the mask, the guide and the output
+        /// symmetry are moved to the middle of the larger grid, and the solver
+        /// settings carry over as they are. Edge matching, required tiles and
+        /// tile caps are about the requested grid as a whole, which the larger
+        /// one can't keep to, so with any of those set this just runs as usual,
+        /// as do periodic models since they have no edges.
+        pub fn run_with_slack(&mut self, seed: u64, margin: usize) -> bool {
+            if self.periodic
+                || margin == 0
+                || self.n > 1
+                || self.edge_matching.is_some()
+                || !self.required_tiles.is_empty()
+                || !self.tile_caps.is_empty()
+            {
+                return self.run(seed, usize::MAX);
+            }
+            let (width, height) = (self.width + 2 * margin, self.height + 2 * margin);
+            let mut larger =
+                Self::from_tileset(self.tileset(), width, height, false, self.heuristic.clone());
+            // The cell of the larger grid that cell `i` of this one ends up as
+            let own_width = self.width;
+            let inner = move |i: usize| i % own_width + margin + (i / own_width + margin) * width;
+            larger.constraints = self
+                .constraints
+                .iter()
+                .map(|(i, allowed)| (inner(*i), allowed.clone()))
+                .collect();
+            if let Some(mask) = &self.mask {
+                let mut larger_mask = vec![true; width * height];
+                for (i, &generated) in mask.iter().enumerate() {
+                    larger_mask[inner(i)] = generated;
+                }
+                larger.mask = Some(larger_mask);
+            }
+            if let Some(guide) = &self.guide {
+                let mut larger_guide = vec![vec![1.; self.num_tiles]; width * height];
+                for (i, weights) in guide.iter().enumerate() {
+                    larger_guide[inner(i)].clone_from(weights);
+                }
+                larger.guide = Some(larger_guide);
+            }
+            // Both grids share their middle, so a symmetric larger grid crops to
+            // a symmetric one
+            larger.output_symmetry = self.output_symmetry;
+            larger.failure_memory = self
+                .failure_memory
+                .iter()
+                .map(|(&(i, t), &failures)| ((inner(i), t), failures))
+                .collect();
+            larger.weight_schedule = self.weight_schedule.take();
+            larger.recovery = self.recovery;
+            larger.adaptive_retries = self.adaptive_retries;
+            larger.batch_observe = self.batch_observe;
+            larger.stop_entropy = self.stop_entropy;
+            larger.tie_break = self.tie_break;
+            larger.deterministic = self.deterministic;
+            larger.frontier_only = self.frontier_only;
+            larger.stack_warning = self.stack_warning;
+
+            let success = larger.run_with_progress(seed, usize::MAX, &ProgressBar::hidden());
+            self.weight_schedule = larger.weight_schedule.take();
+            self.warnings.append(&mut larger.warnings);
+            self.clear();
+            for i in 0..self.wave.len() {
+                let j = inner(i);
+                self.wave[i].clone_from(&larger.wave[j]);
+                self.observed[i] = larger.observed[j];
+            }
+            self.recount();
+            // Openings in the margin have no cell here to be remembered by
+            let outer = |j: usize| {
+                let (x, y) = (j % width, j / width);
+                (x >= margin && x - margin < self.width && y >= margin && y - margin < self.height)
+                    .then(|| x - margin + (y - margin) * self.width)
+            };
+            self.failure_memory = larger
+                .failure_memory
+                .iter()
+                .filter_map(|(&(j, t), &failures)| Some(((outer(j)?, t), failures)))
+                .collect();
+            self.contradiction = larger.contradiction.and_then(outer);
+            self.stats = larger.stats.clone();
+            // A contradiction in the margin shows at the nearest cell of this grid
+            self.stats.contradiction = larger.stats.contradiction.map(|(x, y)| {
+                (
+                    x.saturating_sub(margin).min(self.width - 1),
+                    y.saturating_sub(margin).min(self.height - 1),
+                )
+            });
+            success
+        }
+
+        /// Recomputes the counts kept for every cell from the wave alone, after
+        /// the wave was filled in some other way than by banning tiles.
+        fn recount(&mut self) {
+            for i in 0..self.wave.len() {
+                let possible = || (0..self.num_tiles).filter(|&t| self.wave[i][t]);
+                let sum: f64 = possible().map(|t| self.tiles[t].weight).sum();
+                let sum_log: f64 = possible().map(|t| self.weight_log_weights[t]).sum();
+                let entropy = sum.ln() - sum_log / sum;
+                self.sums_of_ones[i] = possible().count() as isize;
+                self.sums_of_weights[i] = sum;
+                self.sums_of_weight_log_weights[i] = sum_log;
+                self.entropies[i] = if self.sums_of_ones[i] > 0 && sum > 0. && entropy.is_finite() {
+                    entropy
+                } else {
+                    0.
+                };
+                for direction in Direction::ALL {
+                    let opp = direction.opposite().index();
+                    // Masked cells never ban anything, like missing ones
+                    let source = self
+                        .neighbor(i, direction.opposite())
+                        .filter(|&source| !self.masked(source));
+                    for t in 0..self.num_tiles {
+                        let supports = &self.propagator[opp][t];
+                        self.compatible[i][t][direction.index()] = match source {
+                            _ if !self.wave[i][t] => 0,
+                            Some(source) => {
+                                supports.iter().filter(|&&s| self.wave[source][s]).count() as isize
+                            }
+                            None => supports.len() as isize,
+                        };
+                    }
+                }
+            }
+        }
+
+        /// A copy of everything this model was built from, to build another
+        /// size from.
+        fn tileset(&self) -> Tileset {
+            Tileset {
+                tiles: self.tiles.clone(),
+                base_tiles: self.base_tiles.clone(),
+                tile_names: self.tile_names.clone(),
+                mapped_ids: self.mapped_ids.clone(),
+                tile_flags: self.tile_flags.clone(),
+                propagator: self.propagator.clone(),
+                action: self.action.clone(),
+            }
+        }
+
+        /// Replaces the images of the named tiles (and so of all their variants)
+        /// while keeping the solved layout, so [`Model::save`] renders the same
+        /// grid with the new images.
+        ///
+        /// Tiles missing from `images` keep their current image.
+        pub fn reskin(
+            &mut self,
+            mut images: HashMap<String, DynamicImage>,
+        ) -> Result<(), Box<dyn Error>> {
+            for (name, image) in &images {
+                if !self.base_tiles.iter().any(|base| &base.name == name) {
+                    Err(format!("Unknown tile '{name}'"))?;
+                }
+                if image.width() as usize != self.tile_width
+                    || image.height() as usize != self.tile_height
+                {
+                    Err(format!(
+                        "Tile '{name}' is {}x{} pixels but the tileset uses {}x{} pixels",
+                        image.width(),
+                        image.height(),
+                        self.tile_width,
+                        self.tile_height
+                    ))?;
+                }
+            }
+
+            for base in &self.base_tiles {
+                if let Some(image) = images.remove(&base.name) {
+                    let tile = TileObject {
+                        image,
+                        weight: self.tiles[base.first].weight,
+                    };
+                    for (i, variant) in tile.variants(base.symmetry).into_iter().enumerate() {
+                        self.tiles[base.first + i] = variant;
+                    }
+                }
+            }
+            self.average_colors = OnceCell::new();
+            Ok(())
+        }
+
+        /// Cross-fades the `edge_blend` pixels on either side of every seam between
+        /// tiles with their mirror images across the seam, first along rows and
+        /// then along columns. Right at a seam both tiles contribute equally.
+        fn feather_seams(&self, image: &mut RgbaImage) {
+            for horizontal in [true, false] {
+                let (tile, length) = if horizontal {
+                    (self.tile_width as u32, image.width())
+                } else {
+                    (self.tile_height as u32, image.height())
+                };
+                let blend = self.edge_blend.min(tile / 2);
+                if blend == 0 {
+                    continue;
+                }
+                let source = image.clone();
+                for (x, y, pixel) in image.enumerate_pixels_mut() {
+                    let along = if horizontal { x } else { y };
+                    let local = along % tile;
+                    // Distance to the nearest seam and the pixel mirrored across it
+                    let (distance, mirrored) = if local < tile / 2 {
+                        (local, along as i64 - 1 - 2 * local as i64)
+                    } else {
+                        let distance = tile - 1 - local;
+                        (distance, along as i64 + 1 + 2 * distance as i64)
+                    };
+                    if distance >= blend
+                        || (!self.periodic && (mirrored < 0 || mirrored >= length as i64))
+                    {
+                        continue;
+                    }
+                    let mirrored = mirrored.rem_euclid(length as i64) as u32;
+                    let other = if horizontal {
+                        source.get_pixel(mirrored, y)
+                    } else {
+                        source.get_pixel(x, mirrored)
+                    };
+                    let weight = 0.5 * (1. - (distance as f64 + 0.5) / blend as f64);
+                    for (channel, other) in pixel.0.iter_mut().zip(other.0) {
+                        *channel =
+                            (*channel as f64 * (1. - weight) + other as f64 * weight).round() as u8;
+                    }
+                }
+            }
+        }
+
+        /// The mean color of every pixel of tile `index`, alpha included.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `index` is not a tile of this model.
+        pub fn tile_average_color(&self, index: usize) -> [u8; 4] {
+            self.average_colors.get_or_init(|| {
+                self.tiles
+                    .iter()
+                    .map(|tile| {
+                        let image = tile.image.to_rgba8();
+                        let mut sums = [0u64; 4];
+                        for pixel in image.pixels() {
+                            for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+                                *sum += channel as u64;
+                            }
+                        }
+                        let count = (image.width() as u64 * image.height() as u64).max(1);
+                        sums.map(|sum| ((sum + count / 2) / count) as u8)
+                    })
+                    .collect()
+            })[index]
+        }
+
+        /// The per-channel median color of tile `index`, which unlike the average
+        /// ignores small details in a contrasting color.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `index` is not a tile of this model.
+        pub fn tile_median_color(&self, index: usize) -> [u8; 4] {
+            let image = self.tiles[index].image.to_rgba8();
+            let mut median = [0; 4];
+            for (c, median) in median.iter_mut().enumerate() {
+                let mut channel: Vec<u8> = image.pixels().map(|pixel| pixel.0[c]).collect();
+                channel.sort_unstable();
+                *median = channel.get(channel.len() / 2).copied().unwrap_or_default();
+            }
+            median
+        }
+
+        /// Lays out every tile variant on a white sheet in index order, each
+        /// labeled with its index and name, e.g. `4:corner 3`.
+        pub fn tile_atlas(&self) -> RgbaImage {
+            const PAD: u32 = 2;
+            let labels: Vec<String> = self
+                .tile_names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| format!("{i}:{name}"))
+                .collect();
+            let inner_width = labels
+                .iter()
+                .map(|label| text_width(label))
+                .max()
+                .unwrap_or(0)
+                .max(self.tile_width as u32);
+            let cell_width = inner_width + 2 * PAD;
+            let cell_height = self.tile_height as u32 + GLYPH_HEIGHT + 3 * PAD;
+            let columns = (self.num_tiles as f64).sqrt().ceil().max(1.) as u32;
+            let rows = (self.num_tiles as u32).div_ceil(columns);
+
+            let mut atlas = RgbaImage::from_pixel(
+                columns * cell_width,
+                rows * cell_height,
+                Rgba([255, 255, 255, 255]),
+            );
+            for (i, (tile, label)) in self.tiles.iter().zip(&labels).enumerate() {
+                let x = i as u32 % columns * cell_width + PAD;
+                let y = i as u32 / columns * cell_height + PAD;
+                imageops::overlay(
+                    &mut atlas,
+                    &tile.image.to_rgba8(),
+                    (x + (inner_width - self.tile_width as u32) / 2) as i64,
+                    y as i64,
+                );
+                draw_text(
+                    &mut atlas,
+                    x,
+                    y + self.tile_height as u32 + PAD,
+                    label,
+                    Rgba([0, 0, 0, 255]),
+                );
+            }
+            atlas
+        }
+
+        /// Lays the rendered outputs out on a white sheet in a near-square grid, each
+        /// centered in its slot above its label, for comparing tilesets side by side.
+        pub fn comparison_sheet(
+            entries: &[(&str, &SimpleTiled)],
+        ) -> Result<RgbaImage, Box<dyn Error>> {
+            const PAD: u32 = 2;
+            let images = entries
+                .iter()
+                .map(|(_, model)| model.render())
+                .collect::<Result<Vec<_>, _>>()?;
+            let inner_width = entries
+                .iter()
+                .zip(&images)
+                .map(|((label, _), image)| text_width(label).max(image.width()))
+                .max()
+                .unwrap_or(0);
+            let inner_height = images.iter().map(|image| image.height()).max().unwrap_or(0);
+            let cell_width = inner_width + 2 * PAD;
+            let cell_height = inner_height + GLYPH_HEIGHT + 3 * PAD;
+            let columns = (entries.len() as f64).sqrt().ceil().max(1.) as u32;
+            let rows = (entries.len() as u32).div_ceil(columns);
+
+            let mut sheet = RgbaImage::from_pixel(
+                columns * cell_width,
+                rows * cell_height,
+                Rgba([255, 255, 255, 255]),
+            );
+            for (i, ((label, _), image)) in entries.iter().zip(&images).enumerate() {
+                let x = i as u32 % columns * cell_width + PAD;
+                let y = i as u32 / columns * cell_height + PAD;
+                imageops::overlay(
+                    &mut sheet,
+                    image,
+                    (x + (inner_width - image.width()) / 2) as i64,
+                    (y + (inner_height - image.height()) / 2) as i64,
+                );
+                draw_text(
+                    &mut sheet,
+                    x + (inner_width - text_width(label)) / 2,
+                    y + inner_height + PAD,
+                    label,
+                    Rgba([0, 0, 0, 255]),
+                );
+            }
+            Ok(sheet)
+        }
+
+        /// Renders one pixel per cell in the average color of its tile, for maps
+        /// too large to composite in full.
+        pub fn render_minimap(&self) -> Result<RgbaImage, Box<dyn Error>> {
+            if self.unobserved() > 0 {
+                return Err("Model is not fully rendered")?;
+            }
+            Ok(RgbaImage::from_fn(
+                self.width as u32,
+                self.height as u32,
+                |x, y| match self.observed[self.index(x as usize, y as usize)] {
+                    Some(tile) => Rgba(self.tile_average_color(tile)),
+                    None => Rgba(self.background),
+                },
+            ))
+        }
+
+        /// Runs the model with `seed` and writes a looping GIF of the collapse to
+        /// `path`, with a frame before the first observation, after every
+        /// `frame_every` observations and at the end.
+        ///
+        /// Collapsed cells show their tile and the others the weighted blend of
+        /// their possible tiles. The animation is written even if the run fails,
+        /// which is then reported as an error.
+        pub fn save_animation(
+            &mut self,
+            path: &Path,
+            seed: u64,
+            frame_every: usize,
+        ) -> Result<(), Box<dyn Error>> {
+            let frame_every = frame_every.max(1);
+            let images: Vec<RgbaImage> = self.tiles.iter().map(|t| t.image.to_rgba8()).collect();
+            let mut rng = self.init(seed)?;
+            let mut frames = vec![self.render_superposition(&images)?];
+            let mut last_frame = 0;
+            let result = loop {
+                let result = self.step(&mut rng);
+                if result != StepResult::Progressed
+                    || self.stats.observations - last_frame >= frame_every
+                {
+                    last_frame = self.stats.observations;
+                    frames.push(self.render_superposition(&images)?);
+                }
+                if result != StepResult::Progressed {
+                    break result;
+                }
+            };
+
+            let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+            encoder.set_repeat(Repeat::Infinite)?;
+            encoder.encode_frames(frames.into_iter().map(|frame| {
+                Frame::from_parts(
+                    frame,
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(ANIMATION_DELAY_MS, 1),
+                )
+            }))?;
+            if result == StepResult::Contradiction {
+                return Err("Propagation failed")?;
+            }
+            Ok(())
+        }
+
+        /// Renders every cell as the weighted blend of its possible tiles from
+        /// `images`, so collapsed cells show their tile.
+        fn render_superposition(&self, images: &[RgbaImage]) -> Result<RgbaImage, Box<dyn Error>> {
+            let (image_width, image_height) =
+                output_dimensions(self.width, self.height, self.tile_width, self.tile_height)?;
+            let mut imgbuf =
+                ImageBuffer::from_pixel(image_width, image_height, Rgba(self.background));
+            let mut cell = RgbaImage::new(self.tile_width as u32, self.tile_height as u32);
+            for i in 0..self.wave.len() {
+                if !self.cell_fits(i) || self.sums_of_weights[i] <= 0. {
+                    continue;
+                }
+                let mut sums = vec![[0.; 4]; self.tile_width * self.tile_height];
+                for (t, image) in images.iter().enumerate() {
+                    if !self.wave[i][t] {
+                        continue;
+                    }
+                    let share = self.tiles[t].weight / self.sums_of_weights[i];
+                    for (sum, pixel) in sums.iter_mut().zip(image.pixels()) {
+                        for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+                            *sum += channel as f64 * share;
+                        }
+                    }
+                }
+                for (pixel, sum) in cell.pixels_mut().zip(&sums) {
+                    *pixel = Rgba(sum.map(|channel| channel.round().clamp(0., 255.) as u8));
+                }
+                let (x, y) = self.coords(i);
+                imgbuf.copy_from(
+                    &cell,
+                    (x * self.tile_width) as u32,
+                    (y * self.tile_height) as u32,
+                )?;
+            }
+            Ok(imgbuf)
+        }
+
+        /// Writes the output as a PNG one row of tiles at a time, so only a single
+        /// band of `tile_height` pixel rows is ever held in memory.
+        ///
+        /// Unlike [`Model::save`] this always encodes PNG, whatever the extension.
+        pub fn save_streaming(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+            if self.unobserved() > 0 {
+                return Err("Model is not fully rendered")?;
+            }
+            if self.edge_blend > 0 {
+                Err("Edge blending is not supported when streaming")?;
+            }
+            let (image_width, image_height) =
+                output_dimensions(self.width, self.height, self.tile_width, self.tile_height)?;
+            let tiles: Vec<RgbaImage> = self.tiles.iter().map(|t| t.image.to_rgba8()).collect();
+
+            let mut encoder = png::Encoder::new(
+                BufWriter::new(File::create(path)?),
+                image_width,
+                image_height,
+            );
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            let mut stream = writer.stream_writer()?;
+
+            let row_bytes = image_width as usize * 4;
+            let tile_bytes = self.tile_width * 4;
+            let mut band = vec![0; row_bytes * self.tile_height];
+            for y in 0..self.height {
+                for (pixel, background) in band.iter_mut().zip(self.background.iter().cycle()) {
+                    *pixel = *background;
+                }
+                for x in 0..self.width {
+                    let Some(t) = self.observed[self.index(x, y)] else {
+                        continue;
+                    };
+                    let tile = &tiles[t];
+                    for (row, tile_row) in tile.as_raw().chunks_exact(tile_bytes).enumerate() {
+                        let start = row * row_bytes + x * tile_bytes;
+                        band[start..start + tile_bytes].copy_from_slice(tile_row);
+                    }
+                }
+                stream.write_all(&band)?;
+            }
+            stream.finish()?;
+            Ok(())
+        }
+
+        /// Renders the solved grid and then scatters decorations over it.
+        ///
+        /// `decorations` maps a tile name to an overlay image and the probability
+        /// of it being drawn, centered, over each cell holding any variant of that
+        /// tile. The same `seed` always decorates the same cells.
+        pub fn render_decorated(
+            &self,
+            decorations: &HashMap<String, (DynamicImage, f64)>,
+            seed: u64,
+        ) -> Result<RgbaImage, Box<dyn Error>> {
+            let mut images = Vec::new();
+            let mut overlays = vec![None; self.num_tiles];
+            for (name, (image, probability)) in decorations {
+                let base = self
+                    .base_tiles
+                    .iter()
+                    .find(|base| &base.name == name)
+                    .ok_or_else(|| format!("Unknown tile '{name}'"))?;
+                if image.width() as usize > self.tile_width
+                    || image.height() as usize > self.tile_height
+                {
+                    Err(format!("Decoration for '{name}' is larger than a tile"))?;
+                }
+                for overlay in &mut overlays[base.first..base.first + base.cardinality] {
+                    *overlay = Some((images.len(), *probability));
+                }
+                images.push(image.to_rgba8());
+            }
+
+            let mut imgbuf = self.render()?;
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let Some(t) = self.observed[self.index(x, y)] else {
+                        continue;
+                    };
+                    if let Some((image, probability)) = overlays[t] {
+                        if unit_f64(&mut rng) < probability {
+                            let overlay = &images[image];
+                            let offset_x = (self.tile_width - overlay.width() as usize) / 2;
+                            let offset_y = (self.tile_height - overlay.height() as usize) / 2;
+                            imageops::overlay(
+                                &mut imgbuf,
+                                overlay,
+                                (x * self.tile_width + offset_x) as i64,
+                                (y * self.tile_height + offset_y) as i64,
+                            );
+                        }
+                    }
+                }
+            }
+            Ok(imgbuf)
+        }
+
+        /// Renders the solved grid and alpha-blends it over `base` with its alpha
+        /// scaled by `opacity`, clamped to `0.0..=1.0`. `base` must be exactly the
+        /// size of the output.
+        pub fn render_over(
+            &self,
+            base: &RgbaImage,
+            opacity: f32,
+        ) -> Result<RgbaImage, Box<dyn Error>> {
+            let mut tiles = self.render()?;
+            if base.dimensions() != tiles.dimensions() {
+                Err(format!(
+                    "Base image is {}x{} pixels but the output is {}x{}",
+                    base.width(),
+                    base.height(),
+                    tiles.width(),
+                    tiles.height()
+                ))?;
+            }
+            let opacity = opacity.clamp(0., 1.);
+            for (pixel, under) in tiles.pixels_mut().zip(base.pixels()) {
+                let alpha = pixel.0[3] as f32 / 255. * opacity;
+                let under_alpha = under.0[3] as f32 / 255. * (1. - alpha);
+                let out_alpha = alpha + under_alpha;
+                for c in 0..3 {
+                    pixel.0[c] = if out_alpha > 0. {
+                        ((pixel.0[c] as f32 * alpha + under.0[c] as f32 * under_alpha) / out_alpha)
+                            .round() as u8
+                    } else {
+                        0
+                    };
+                }
+                pixel.0[3] = (out_alpha * 255.).round() as u8;
+            }
+            Ok(tiles)
+        }
+
+        /// Renders the solved grid under each of the 8 rotations and reflections of
+        /// the whole output and returns the one `score` rates highest, the
+        /// earliest of the unrotated, quarter, half and three quarter turns and
+        /// then their mirror images on a tie. Negate the score to minimize it.
+        pub fn best_orientation(
+            &self,
+            score: impl Fn(&RgbaImage) -> f64,
+        ) -> Result<RgbaImage, Box<dyn Error>> {
+            let image = self.render()?;
+            let mirrored = imageops::flip_horizontal(&image);
+            let mut best: Option<(f64, RgbaImage)> = None;
+            for base in [image, mirrored] {
+                let orientations = [
+                    imageops::rotate90(&base),
+                    imageops::rotate180(&base),
+                    imageops::rotate270(&base),
+                ];
+                for candidate in std::iter::once(base).chain(orientations) {
+                    let candidate_score = score(&candidate);
+                    if best
+                        .as_ref()
+                        .is_none_or(|(best, _)| candidate_score > *best)
+                    {
+                        best = Some((candidate_score, candidate));
+                    }
+                }
+            }
+            Ok(best.unwrap().1)
+        }
+
+        /// Sets the RGBA color the output is filled with before tiles are composited.
+        ///
+        /// Defaults to transparent black.
+        pub fn set_background(&mut self, background: [u8; 4]) {
+            self.background = background;
+        }
+
+        /// Softens the seams between tiles by blending up to `width` pixels on
+        /// each side of them with the neighboring tile, or turns blending off if 0.
+        ///
+        /// The blend is capped at half a tile. Seams across the wrapped edges are
+        /// only blended in periodic outputs.
+        pub fn set_edge_blend(&mut self, width: u32) {
+            self.edge_blend = width;
+        }
+
+        /// Writes the tileset derived from the config (tile images, names and
+        /// adjacencies) to `path` so [`SimpleTiled::from_compiled`] can skip
+        /// parsing and image loading.
+        pub fn compile(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+            let compiled = CompiledTileset {
+                version: COMPILED_VERSION,
+                tiles: self
+                    .tiles
+                    .iter()
+                    .map(|t| {
+                        let image = t.image.to_rgba8();
+                        (image.width(), image.height(), image.into_raw(), t.weight)
+                    })
+                    .collect(),
+                base_tiles: self.base_tiles.clone(),
+                tile_names: self.tile_names.clone(),
+                mapped_ids: self.mapped_ids.as_ref().map(|ids| {
+                    ids.iter()
+                        .map(|id| match id {
+                            MappedId::Number(id) => Ok(*id),
+                            MappedId::Name(id) => Err(id.clone()),
+                        })
+                        .collect()
+                }),
+                tile_flags: self.tile_flags.clone(),
+                propagator: self.propagator.clone(),
+                action: self.action.clone(),
+            };
+            bincode::serialize_into(BufWriter::new(File::create(path)?), &compiled)?;
+            Ok(())
+        }
+
+        /// Creates a model from a tileset written by [`SimpleTiled::compile`].
+        pub fn from_compiled(
+            path: &Path,
+            width: usize,
+            height: usize,
+            periodic: bool,
+            heuristic: Heuristic,
+        ) -> Result<Self, Box<dyn Error>> {
+            let compiled: CompiledTileset =
+                bincode::deserialize_from(BufReader::new(File::open(path)?))?;
+            if compiled.version != COMPILED_VERSION {
+                Err(format!(
+                    "Compiled tileset has version {} but version {COMPILED_VERSION} is required",
+                    compiled.version
+                ))?;
+            }
+            let mut tiles = Vec::with_capacity(compiled.tiles.len());
+            for (tile_width, tile_height, pixels, weight) in compiled.tiles {
+                let image = RgbaImage::from_raw(tile_width, tile_height, pixels)
+                    .ok_or("Compiled tileset holds a truncated tile image")?;
+                tiles.push(TileObject {
+                    image: DynamicImage::ImageRgba8(image),
+                    weight,
+                });
+            }
+            let tileset = Tileset {
+                tiles,
+                base_tiles: compiled.base_tiles,
+                tile_names: compiled.tile_names,
+                mapped_ids: compiled.mapped_ids.map(|ids| {
+                    ids.into_iter()
+                        .map(|id| id.map_or_else(MappedId::Name, MappedId::Number))
+                        .collect()
+                }),
+                tile_flags: compiled.tile_flags,
+                propagator: compiled.propagator,
+                action: compiled.action,
+            };
+            Ok(Self::from_tileset(
+                tileset, width, height, periodic, heuristic,
+            ))
+        }
+
+        /// Slices `image` into tiles, matches each against the tileset pixel for
+        /// pixel and reports every cell that matches nothing or every pair of
+        /// neighbors the rules don't allow.
+        ///
+        /// The grid size comes from the image rather than the model. Adjacency
+        /// across the wrapped edges is checked when the model is periodic.
+        pub fn verify_image(&self, image: &DynamicImage) -> Result<Vec<Violation>, Box<dyn Error>> {
+            let (width, height, cells) = self.slice_cells(image)?;
+            let tiles: Vec<RgbaImage> = self.tiles.iter().map(|t| t.image.to_rgba8()).collect();
+
+            // Tiles that happen to look alike all count as a match
+            let mut matches = Vec::with_capacity(width * height);
+            let mut violations = vec![];
+            for (i, cell) in cells.iter().enumerate() {
+                let candidates: Vec<usize> =
+                    (0..self.num_tiles).filter(|&t| &tiles[t] == cell).collect();
+                if candidates.is_empty() {
+                    violations.push(Violation::UnknownTile {
+                        x: i % width,
+                        y: i / width,
+                    });
+                }
+                matches.push(candidates);
+            }
+
+            for y in 0..height {
+                for x in 0..width {
+                    for (direction, nx, ny) in
+                        [(Direction::Right, x + 1, y), (Direction::Down, x, y + 1)]
+                    {
+                        if !self.periodic && (nx >= width || ny >= height) {
+                            continue;
+                        }
+                        let here = &matches[x + y * width];
+                        let there = &matches[nx % width + ny % height * width];
+                        if here.is_empty() || there.is_empty() {
+                            continue;
+                        }
+                        let allowed = here.iter().any(|&t1| {
+                            there
+                                .iter()
+                                .any(|t2| self.propagator[direction.index()][t1].contains(t2))
+                        });
+                        if !allowed {
+                            violations.push(Violation::Adjacency { x, y, direction });
+                        }
+                    }
+                }
+            }
+            Ok(violations)
+        }
+
+        /// Weights for every tile of the config, in config order, that reproduce
+        /// how often each tile appears in the example `image`.
+        ///
+        /// Every cell counts towards the tile whose average color is closest, so
+        /// the example doesn't need to be an exact tiling. The weights are those
+        /// of each variant, a tile's count divided by its cardinality, which
+        /// [`Config::weight_block`] scales back up under `normalize_weights`.
+        /// Tiles missing from the example keep a hundredth of the smallest
+        /// weight so they are still there when nothing else fits.
+        pub fn example_weights(
+            &self,
+            image: &DynamicImage,
+        ) -> Result<Vec<(String, f64)>, Box<dyn Error>> {
+            let (_, _, cells) = self.slice_cells(image)?;
+            if cells.is_empty() {
+                Err("Example image is empty")?;
+            }
+            let mut counts = vec![0usize; self.num_tiles];
+            for cell in &cells {
+                let mut sums = [0u64; 4];
+                for pixel in cell.pixels() {
+                    for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+                        *sum += channel as u64;
+                    }
+                }
+                let count = (cell.width() as u64 * cell.height() as u64).max(1);
+                let average = sums.map(|sum| ((sum + count / 2) / count) as i64);
+                let distance = |t: usize| -> i64 {
+                    let color = self.tile_average_color(t);
+                    (0..4).map(|c| (average[c] - color[c] as i64).pow(2)).sum()
+                };
+                if let Some(t) = (0..self.num_tiles).min_by_key(|&t| distance(t)) {
+                    counts[t] += 1;
+                }
+            }
+
+            let mut weights: Vec<(String, f64)> = self
+                .base_tiles
+                .iter()
+                .map(|base| {
+                    let count: usize = counts[base.first..base.first + base.cardinality]
+                        .iter()
+                        .sum();
+                    (base.name.clone(), count as f64 / base.cardinality as f64)
+                })
+                .collect();
+            let floor = weights
+                .iter()
+                .map(|(_, weight)| *weight)
+                .filter(|&weight| weight > 0.)
+                .fold(f64::INFINITY, f64::min)
+                / 100.;
+            for (_, weight) in &mut weights {
+                if *weight == 0. {
+                    *weight = floor;
+                }
+            }
+            Ok(weights)
+        }
+
+        /// Cuts `image` into tile sized cells in row-major order, returning the
+        /// grid size alongside them.
+        fn slice_cells(
+            &self,
+            image: &DynamicImage,
+        ) -> Result<(usize, usize, Vec<RgbaImage>), Box<dyn Error>> {
+            let (tile_width, tile_height) = (self.tile_width as u32, self.tile_height as u32);
+            if image.width() % tile_width != 0 || image.height() % tile_height != 0 {
+                Err(format!(
+                    "Image of {}x{} pixels isn't a whole number of {tile_width}x{tile_height} tiles",
+                    image.width(),
+                    image.height()
+                ))?;
+            }
+            let width = (image.width() / tile_width) as usize;
+            let height = (image.height() / tile_height) as usize;
+            let image = image.to_rgba8();
+            let mut cells = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    cells.push(
+                        imageops::crop_imm(
+                            &image,
+                            x as u32 * tile_width,
+                            y as u32 * tile_height,
+                            tile_width,
+                            tile_height,
+                        )
+                        .to_image(),
+                    );
+                }
+            }
+            Ok((width, height, cells))
+        }
+
+        /// Captures the current solving state so it can be returned to later.
+        pub fn snapshot(&self) -> ModelSnapshot {
+            ModelSnapshot {
+                wave: self.wave.clone(),
+                compatible: self.compatible.clone(),
+                observed: self.observed.clone(),
+                stack: self.stack.clone(),
+                observed_so_far: self.observed_so_far,
+                sums_of_ones: self.sums_of_ones.clone(),
+                sums_of_weights: self.sums_of_weights.clone(),
+                sums_of_weight_log_weights: self.sums_of_weight_log_weights.clone(),
+                entropies: self.entropies.clone(),
+                contradiction: self.contradiction,
+                frontier: self.frontier.clone(),
+                events: self.events.clone(),
+            }
+        }
+
+        /// Returns to a state captured by [`SimpleTiled::snapshot`], reusing the
+        /// existing allocations.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the snapshot was taken from a model of a different size or
+        /// tileset.
+        pub fn restore(&mut self, snapshot: &ModelSnapshot) {
+            assert!(
+                snapshot.wave.len() == self.wave.len()
+                    && snapshot.wave.first().map(Vec::len) == self.wave.first().map(Vec::len),
+                "Snapshot does not match the model's dimensions"
+            );
+            self.wave.clone_from(&snapshot.wave);
+            self.compatible.clone_from(&snapshot.compatible);
+            self.observed.clone_from(&snapshot.observed);
+            self.stack.clone_from(&snapshot.stack);
+            self.observed_so_far = snapshot.observed_so_far;
+            self.sums_of_ones.clone_from(&snapshot.sums_of_ones);
+            self.sums_of_weights.clone_from(&snapshot.sums_of_weights);
+            self.sums_of_weight_log_weights
+                .clone_from(&snapshot.sums_of_weight_log_weights);
+            self.entropies.clone_from(&snapshot.entropies);
+            self.contradiction = snapshot.contradiction;
+            self.frontier.clone_from(&snapshot.frontier);
+            self.events.clone_from(&snapshot.events);
+        }
+
+        /// Restricts the cells along `side` so that the output lines up with the
+        /// already solved `neighbor` lying on that side.
+        ///
+        /// Cells of `neighbor` left out by its mask don't restrict anything. The
+        /// restriction is applied at the start of every subsequent run until
+        /// [`SimpleTiled::clear_constraints`] is called.
+        pub fn constrain_edge_from(
+            &mut self,
+            neighbor: &SimpleTiled,
+            side: Direction,
+        ) -> Result<(), Box<dyn Error>> {
+            if neighbor.tile_names != self.tile_names {
+                Err("Neighboring model uses a different tileset")?;
+            }
+            let horizontal = matches!(side, Direction::Left | Direction::Right);
+            if horizontal && neighbor.height != self.height {
+                Err(format!(
+                    "Neighboring model is {} tiles high but this model is {} tiles high",
+                    neighbor.height, self.height
+                ))?;
+            } else if !horizontal && neighbor.width != self.width {
+                Err(format!(
+                    "Neighboring model is {} tiles wide but this model is {} tiles wide",
+                    neighbor.width, self.width
+                ))?;
+            }
+            if neighbor.unobserved() > 0 {
+                Err("Neighboring model is not fully rendered")?;
+            }
+
+            let edge_length = if horizontal { self.height } else { self.width };
+            for k in 0..edge_length {
+                let (own, theirs) = match side {
+                    Direction::Left => (self.index(0, k), neighbor.index(neighbor.width - 1, k)),
+                    Direction::Right => (self.index(self.width - 1, k), neighbor.index(0, k)),
+                    Direction::Up => (self.index(k, 0), neighbor.index(k, neighbor.height - 1)),
+                    Direction::Down => (self.index(k, self.height - 1), k),
+                };
+                let Some(neighbor_tile) = neighbor.observed[theirs] else {
+                    continue;
+                };
+                let mut allowed = vec![false; self.num_tiles];
+                for &t in &self.propagator[side.opposite().index()][neighbor_tile] {
+                    allowed[t] = true;
+                }
+                self.constraints.push((own, allowed));
+            }
+            Ok(())
+        }
+
+        /// Pins cell `(x, y)` to `tile_name` at the start of every subsequent run
+        /// until [`SimpleTiled::clear_constraints`] is called, banning every other
+        /// tile there before the first observation and propagating the bans.
+        ///
+        /// A plain tile name allows any of its variants while `"name 2"` requires
+        /// that exact variant. Errors without pinning if the pin contradicts the
+        /// rules or the other constraints.
+        pub fn set_tile(
+            &mut self,
+            x: usize,
+            y: usize,
+            tile_name: &str,
+        ) -> Result<(), Box<dyn Error>> {
+            if x >= self.width || y >= self.height {
+                Err(format!(
+                    "Cell ({x}, {y}) is outside the {}x{} grid",
+                    self.width, self.height
+                ))?;
+            }
+            let allowed = self.selected_tiles(tile_name)?;
+            self.constraints.push((self.index(x, y), allowed));
+            if let Err(contradiction) = self.check_constraints() {
+                self.constraints.pop();
+                Err(match contradiction {
+                    Some((cx, cy)) => format!(
+                        "Pinning '{tile_name}' at ({x}, {y}) leaves no tile possible at ({cx}, {cy})"
+                    ),
+                    None => format!("Pinning '{tile_name}' at ({x}, {y}) contradicts the other constraints"),
+                })?;
+            }
+            Ok(())
+        }
+
+        /// Pins every cell on the edge of the grid to `tile_name` like
+        /// [`SimpleTiled::set_tile`], so the output fits into a frame of that tile.
+        ///
+        /// Periodic grids have no edge, so this errors for them.
+        pub fn set_border(&mut self, tile_name: &str) -> Result<(), Box<dyn Error>> {
+            if self.periodic {
+                Err("A periodic grid has no border to pin")?;
+            }
+            let allowed = self.selected_tiles(tile_name)?;
+            let added = self.constraints.len();
+            for i in 0..self.wave.len() {
+                let (x, y) = self.coords(i);
+                if x == 0 || y == 0 || x == self.width - 1 || y == self.height - 1 {
+                    self.constraints.push((i, allowed.clone()));
+                }
+            }
+            if let Err(contradiction) = self.check_constraints() {
+                self.constraints.truncate(added);
+                Err(match contradiction {
+                    Some((cx, cy)) => {
+                        format!("A border of '{tile_name}' leaves no tile possible at ({cx}, {cy})")
+                    }
+                    None => format!("A border of '{tile_name}' contradicts the other constraints"),
+                })?;
+            }
+            Ok(())
+        }
+
+        /// Applies the constraints to a cleared wave and returns where they
+        /// contradict each other, if they do, leaving the model as it was.
+        fn check_constraints(&mut self) -> Result<(), Option<(usize, usize)>> {
+            let (snapshot, stats) = (self.snapshot(), self.stats.clone());
+            self.clear();
+            let consistent = self.apply_constraints();
+            let contradiction = self.contradiction.map(|i| self.coords(i));
+            self.restore(&snapshot);
+            self.stats = stats;
+            if consistent {
+                Ok(())
+            } else {
+                Err(contradiction)
+            }
+        }
+
+        /// Removes every constraint added to this model.
+        pub fn clear_constraints(&mut self) {
+            self.constraints.clear();
+        }
+
+        /// Guarantees that each of `names` appears at least once in every following
+        /// run by pinning it to a random cell before observation begins.
+        ///
+        /// A plain tile name allows any of its variants while `"name 2"` requires
+        /// that exact variant.
+        pub fn set_required_tiles(&mut self, names: &[&str]) -> Result<(), Box<dyn Error>> {
+            let mut required_tiles = Vec::with_capacity(names.len());
+            for &name in names {
+                required_tiles.push((name.to_string(), self.selected_tiles(name)?));
+            }
+            self.required_tiles = required_tiles;
+            Ok(())
+        }
+
+        /// Which tiles `name` refers to, any variant of a plain tile name or the
+        /// single variant of `"name 2"`.
+        fn selected_tiles(&self, name: &str) -> Result<Vec<bool>, Box<dyn Error>> {
+            let tile_name: Vec<&str> = name.split(' ').collect();
+            let base = self
+                .base_tiles
+                .iter()
+                .find(|base| base.name == tile_name[0])
+                .ok_or_else(|| format!("Unknown tile '{}'", tile_name[0]))?;
+            let variants = match tile_name.get(1) {
+                Some(orientation) => {
+                    let orientation: usize = orientation.parse()?;
+                    if orientation >= base.cardinality {
+                        Err(format!(
+                            "Tile '{}' only has {} variants",
+                            base.name, base.cardinality
+                        ))?;
+                    }
+                    base.first + orientation..base.first + orientation + 1
+                }
+                None => base.first..base.first + base.cardinality,
+            };
+            let mut allowed = vec![false; self.num_tiles];
+            for t in variants {
+                allowed[t] = true;
+            }
+            Ok(allowed)
+        }
+
+        /// Caps how much of the output each tile of `caps` may cover, as a
+        /// fraction of the generated cells. Tiles are named like in
+        /// [`SimpleTiled::set_required_tiles`].
+        ///
+        /// After every observation a tile that reached its cap is banned from all
+        /// the cells that haven't collapsed yet. Propagation can still collapse a
+        /// few more cells to it in the same step, so the cap is a close bound
+        /// rather than an exact one. Tight caps make contradictions more likely,
+        /// and cells reset by [`Recovery::ResetCluster`] may hold the tile again
+        /// until the next observation.
+        pub fn set_tile_caps(&mut self, caps: &[(&str, f64)]) -> Result<(), Box<dyn Error>> {
+            let mut tile_caps = Vec::with_capacity(caps.len());
+            for &(name, fraction) in caps {
+                if !(0. ..=1.).contains(&fraction) {
+                    Err(format!(
+                        "Cap {fraction} for '{name}' is not between 0 and 1"
+                    ))?;
+                }
+                tile_caps.push((self.selected_tiles(name)?, fraction));
+            }
+            self.tile_caps = tile_caps;
+            Ok(())
+        }
+
+        /// How many cells have collapsed to each tile so far.
+        pub fn tile_histogram(&self) -> Vec<usize> {
+            let mut histogram = vec![0; self.num_tiles];
+            for (wave, &remaining) in self.wave.iter().zip(&self.sums_of_ones) {
+                if remaining == 1 {
+                    if let Some(t) = wave.iter().position(|&possible| possible) {
+                        histogram[t] += 1;
+                    }
+                }
+            }
+            histogram
+        }
+
+        /// Makes every following run mirror its observations so the whole output
+        /// has `symmetry`, or removes the requirement if `None`.
+        ///
+        /// Runs fail like any other contradiction when the tileset can't meet it.
+        pub fn set_output_symmetry(&mut self, symmetry: Option<OutputSymmetry>) {
+            self.output_symmetry = symmetry;
+        }
+
+        /// Makes every following run keep the chosen pairs of opposite edges
+        /// identical, or stops doing so if `None`.
+        ///
+        /// Unlike a periodic output, whose edges continue into each other, copies
+        /// of the output then tile when they overlap by one row or column.
+        pub fn set_edge_matching(&mut self, matching: Option<EdgeMatching>) {
+            self.edge_matching = matching;
+        }
+
+        /// Chooses how following runs react to a contradiction. Defaults to
+        /// [`Recovery::Restart`].
+        ///
+        /// [`Recovery::ResetCluster`] resets the contradicted cell and its
+        /// neighbors and keeps going, which is much cheaper than restarting on
+        /// large grids but doesn't explore alternatives the way backtracking would.
+        pub fn set_recovery(&mut self, recovery: Recovery) {
+            self.recovery = recovery;
+        }
+
+        /// Makes failed runs steer the following ones away from the observations
+        /// they opened with, or stops doing so and forgets what was learned.
+        ///
+        /// The tiles chosen by the first few observations of a failed run become
+        /// less likely at those cells, more so the more often they failed. The
+        /// memory lives until a run succeeds, so it only shapes the retries of one
+        /// output, and is otherwise only dropped by
+        /// [`SimpleTiled::clear_failure_memory`] or by turning this off. While it
+        /// holds anything a seed no longer reproduces the same output.
+        pub fn set_adaptive_retries(&mut self, adaptive: bool) {
+            self.adaptive_retries = adaptive;
+            if !adaptive {
+                self.clear_failure_memory();
+            }
+        }
+
+        /// Forgets which opening observations failed before, see
+        /// [`SimpleTiled::set_adaptive_retries`].
+        pub fn clear_failure_memory(&mut self) {
+            self.failure_memory.clear();
+        }
+
+        /// Observes up to `count` cells, none next to another, between propagations
+        /// instead of one. 0 and 1 both observe one cell at a time.
+        ///
+        /// This only applies to the entropy and MRV heuristics. The later cells of
+        /// a batch are picked and collapsed without seeing the consequences of the
+        /// earlier ones, so large batches stray from the heuristic's order and
+        /// contradict more often, though a contradiction anywhere in the batch
+        /// still fails the run.
+        pub fn set_batch_observe(&mut self, count: usize) {
+            self.batch_observe = count.max(1);
+        }
+
+        /// Multiplies the weight of every tile by `schedule(tile, fraction)` when
+        /// observing, where `fraction` is the share of cells already collapsed,
+        /// e.g. to pick large structures first and details around them later.
+        ///
+        /// The scaled weights only steer observations, entropies keep using the
+        /// configured ones. The penalties of [`SimpleTiled::set_adaptive_retries`]
+        /// apply on top.
+        pub fn set_weight_schedule(&mut self, schedule: impl Fn(usize, f64) -> f64 + 'static) {
+            self.weight_schedule = Some(WeightSchedule(Box::new(schedule)));
+        }
+
+        /// Goes back to observing with the configured weights.
+        pub fn clear_weight_schedule(&mut self) {
+            self.weight_schedule = None;
+        }
+
+        /// Only generates the cells that are `true` in `mask`, row by row from the
+        /// top, or every cell for `None`.
+        ///
+        /// Left out cells stay unobserved and render as the background. They don't
+        /// constrain the cells around them, so tiles there needn't continue into
+        /// the gaps.
+        pub fn set_mask(&mut self, mask: Option<Vec<bool>>) -> Result<(), Box<dyn Error>> {
+            if let Some(mask) = &mask {
+                if mask.len() != self.wave.len() {
+                    Err(format!(
+                        "Mask has {} cells but the grid has {}",
+                        mask.len(),
+                        self.wave.len()
+                    ))?;
+                }
+            }
+            self.mask = mask;
+            Ok(())
+        }
+
+        /// Reads a mask for [`SimpleTiled::set_mask`] from an image, leaving out
+        /// the cells whose pixel is fully transparent or, if given, exactly `color`.
+        ///
+        /// The image is stretched over the grid and each cell samples the pixel
+        /// under its center, so an image with one pixel per cell or one the size
+        /// of the output both map cell for cell. Other sizes work too, but cells
+        /// straddling an edge of the painted region go whichever way their center
+        /// falls.
+        pub fn mask_from_image(&self, image: &DynamicImage, color: Option<[u8; 3]>) -> Vec<bool> {
+            self.cell_pixels(image)
+                .into_iter()
+                .map(|Rgba([r, g, b, a])| a > 0 && color != Some([r, g, b]))
+                .collect()
+        }
+
+        /// The pixel of `image`, stretched over the grid, under the center of
+        /// every cell.
+        fn cell_pixels(&self, image: &DynamicImage) -> Vec<Rgba<u8>> {
+            let image = image.to_rgba8();
+            (0..self.wave.len())
+                .map(|i| {
+                    let (x, y) = self.coords(i);
+                    let px = ((2 * x + 1) * image.width() as usize / (2 * self.width)) as u32;
+                    let py = ((2 * y + 1) * image.height() as usize / (2 * self.height)) as u32;
+                    *image.get_pixel(px, py)
+                })
+                .collect()
+        }
+
+        /// Biases every cell towards the tiles whose average color is closest to
+        /// the pixel of `guide` under it, to paint the broad strokes of the output
+        /// with a small sketch. `guide` is stretched over the grid like in
+        /// [`SimpleTiled::mask_from_image`] and transparent pixels leave their
+        /// cells alone.
+        ///
+        /// Each weight is multiplied by `exp(-strength * distance)`, with the RGB
+        /// distance scaled to `0..=1`, on top of [`SimpleTiled::set_weight_schedule`].
+        /// A `strength` of 0 removes the guide.
+        pub fn set_guide(&mut self, guide: &DynamicImage, strength: f64) {
+            if strength == 0. {
+                self.guide = None;
+                return;
+            }
+            let colors: Vec<[u8; 4]> = (0..self.num_tiles)
+                .map(|t| self.tile_average_color(t))
+                .collect();
+            let guide = self
+                .cell_pixels(guide)
+                .into_iter()
+                .map(|Rgba(pixel)| {
+                    if pixel[3] == 0 {
+                        return vec![1.; colors.len()];
+                    }
+                    colors
+                        .iter()
+                        .map(|color| {
+                            let distance = pixel
+                                .iter()
+                                .zip(color)
+                                .take(3)
+                                .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+                                .sum::<f64>()
+                                .sqrt()
+                                / (3. * 255f64.powi(2)).sqrt();
+                            (-strength * distance).exp()
+                        })
+                        .collect()
+                })
+                .collect();
+            self.guide = Some(guide);
+        }
+
+        /// Stops observing once the mean entropy of the cells that still have a
+        /// choice drops below `threshold`, leaving each of them as its first
+        /// possible tile. Those cells needn't fit their neighbors, so higher
+        /// thresholds give fuzzier output. `None` collapses every cell.
+        pub fn set_stop_entropy(&mut self, threshold: Option<f64>) {
+            self.stop_entropy = threshold;
+        }
+
+        /// Picks the next cells to observe with `heuristic` from the next run on.
+        pub fn set_heuristic(&mut self, heuristic: Heuristic) {
+            self.heuristic = heuristic;
+        }
+
+        /// Whether observed cells take their heaviest possible tile, the first one
+        /// of equal weight, instead of a random one. With [`Heuristic::ScanLine`]
+        /// and no required tiles every seed then gives the same output.
+        pub fn set_deterministic(&mut self, deterministic: bool) {
+            self.deterministic = deterministic;
+        }
+
+        /// How the MRV heuristic picks among the cells with equally few possible
+        /// tiles, which steers where the collapse spreads. Other heuristics ignore it.
+        pub fn set_tie_break(&mut self, tie_break: TieBreak) {
+            self.tie_break = tie_break;
+        }
+
+        /// Makes the entropy and MRV heuristics only observe cells next to an
+        /// already collapsed cell once there is one, so the output grows outwards
+        /// from its first observation instead of collapsing in scattered places.
+        ///
+        /// Scan line observation already grows from a corner and is unaffected.
+        pub fn set_frontier_only(&mut self, frontier_only: bool) {
+            self.frontier_only = frontier_only;
+        }
+
+        /// Warns after a run whose propagation stack grew beyond `threshold`
+        /// entries, or never if `None`.
+        pub fn set_stack_warning(&mut self, threshold: Option<usize>) {
+            self.stack_warning = threshold;
+        }
+
+        /// Takes the warnings found since the last call, from building the
+        /// model, like redundant neighbor rules or tiles without neighbors, and
+        /// from runs, like the one of [`SimpleTiled::set_stack_warning`]. The
+        /// model never prints them itself.
+        pub fn take_warnings(&mut self) -> Vec<String> {
+            std::mem::take(&mut self.warnings)
+        }
+
+        /// Whether [`Model::run`] draws a progress bar in the terminal. Off by
+        /// default, so runs do no terminal I/O.
+        pub fn set_progress(&mut self, progress: bool) {
+            self.progress = progress;
+        }
+
+        /// Renders one pixel per cell showing how many tiles it still allows, from
+        /// black for a collapsed cell to white for an untouched one.
+        ///
+        /// Cells left without any possible tile are drawn red.
+        pub fn render_entropy_map(&self) -> RgbaImage {
+            RgbaImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+                let remaining = self.sums_of_ones[self.index(x as usize, y as usize)];
+                if remaining <= 0 {
+                    Rgba([255, 0, 0, 255])
+                } else {
+                    let openness = (remaining - 1) as f64 / (self.num_tiles.max(2) - 1) as f64;
+                    let intensity = (openness * 255.).round() as u8;
+                    Rgba([intensity, intensity, intensity, 255])
+                }
+            })
+        }
+
+        /// The names of all tile variants, such as `"corner 2"`, by tile index.
+        pub fn tile_names(&self) -> &[String] {
+            &self.tile_names
+        }
+
+        /// The tile index observed at every cell, listed in `order`, or `None` for
+        /// cells that haven't collapsed.
+        ///
+        /// The model itself always stores cells row-major.
+        pub fn observed_tiles(&self, order: GridOrder) -> Vec<Option<usize>> {
+            self.lines(order)
+                .into_iter()
+                .flatten()
+                .map(|i| self.observed[i])
+                .collect()
+        }
+
+        /// Formats the solved grid like [`Display`], with one line per row or
+        /// column of `order`.
+        pub fn format_grid(&self, order: GridOrder) -> String {
+            let unobserved = self.unobserved();
+            if unobserved > 0 {
+                return format!("{unobserved} unobserved tiles");
+            }
+            let mut grid = String::new();
+            for line in self.lines(order) {
+                for i in line {
+                    if let Some(t) = self.observed[i] {
+                        grid += &self.tile_names[t];
+                    }
+                    grid += ",\t";
+                }
+                grid += "\n";
+            }
+            grid
+        }
+
+        /// Formats the solved grid with a single character per cell, the same for
+        /// every variant of a tile, followed by a legend of the characters. Used
+        /// by the alternate form of [`Display`], `{:#}`.
+        ///
+        /// Tiles get the first character of their name unless another tile took it
+        /// already. Cells left out by the mask are blank.
+        pub fn format_compact(&self, order: GridOrder) -> String {
+            let unobserved = self.unobserved();
+            if unobserved > 0 {
+                return format!("{unobserved} unobserved tiles");
+            }
+            const FALLBACK: &str =
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789#@%&+=*";
+            let mut symbols: Vec<char> = vec![];
+            for base in &self.base_tiles {
+                let symbol = base
+                    .name
+                    .chars()
+                    .next()
+                    .filter(|c| !c.is_whitespace() && !symbols.contains(c))
+                    .or_else(|| FALLBACK.chars().find(|c| !symbols.contains(c)))
+                    .unwrap_or('?');
+                symbols.push(symbol);
+            }
+            let mut bases = vec![0; self.num_tiles];
+            for (index, base) in self.base_tiles.iter().enumerate() {
+                bases[base.first..base.first + base.cardinality].fill(index);
+            }
+
+            let mut used = vec![false; self.base_tiles.len()];
+            let mut grid = String::new();
+            for line in self.lines(order) {
+                for i in line {
+                    grid.push(match self.observed[i] {
+                        Some(t) => {
+                            used[bases[t]] = true;
+                            symbols[bases[t]]
+                        }
+                        None => ' ',
+                    });
+                }
+                grid += "\n";
+            }
+            for (index, base) in self.base_tiles.iter().enumerate() {
+                if used[index] {
+                    grid += &format!("\n{} = {}", symbols[index], base.name);
+                }
+            }
+            grid += "\n";
+            grid
+        }
+
+        /// The cell indices of every row or column, in `order`.
+        fn lines(&self, order: GridOrder) -> Vec<Vec<usize>> {
+            let row = |y: usize| (0..self.width).map(|x| self.index(x, y)).collect();
+            match order {
+                GridOrder::RowMajor => (0..self.height).map(row).collect(),
+                GridOrder::YUp => (0..self.height).rev().map(row).collect(),
+                GridOrder::ColumnMajor => (0..self.width)
+                    .map(|x| (0..self.height).map(|y| self.index(x, y)).collect())
+                    .collect(),
+            }
+        }
+
+        /// The `[mapping]` ID of every observed tile, row by row from the top, or
+        /// `None` if the config has no mapping or a cell is unobserved.
+        pub fn observed_mapped(&self) -> Option<Vec<Vec<MappedId>>> {
+            let ids = self.mapped_ids.as_ref()?;
+            self.lines(GridOrder::RowMajor)
+                .into_iter()
+                .map(|line| {
+                    line.into_iter()
+                        .map(|i| Some(ids[self.observed[i]?].clone()))
+                        .collect()
+                })
+                .collect()
+        }
+
+        /// Whether the observed tile of every cell has `flag` set in `[flags]`, row
+        /// by row from the top, e.g. a collision map from `walkable`. Tiles without
+        /// the flag count as false. `None` if no tile has the flag or a cell is
+        /// unobserved.
+        pub fn flag_grid(&self, flag: &str) -> Option<Vec<Vec<bool>>> {
+            if !self.tile_flags.iter().any(|flags| flags.contains_key(flag)) {
+                return None;
+            }
+            self.lines(GridOrder::RowMajor)
+                .into_iter()
+                .map(|line| {
+                    line.into_iter()
+                        .map(|i| {
+                            let flags = &self.tile_flags[self.observed[i]?];
+                            Some(flags.get(flag).copied().unwrap_or(false))
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+
+        /// Calls `placed` with `(x, y, tile)` for every collapsed cell holding one of
+        /// the tiles `names` refers to, like `"chest"` for any variant or `"door 1"`
+        /// for one, row by row from the top. With no names every collapsed cell
+        /// counts, e.g. to collect spawn points straight from the generated grid.
+        pub fn for_each_placed(
+            &self,
+            names: &[&str],
+            mut placed: impl FnMut(usize, usize, usize),
+        ) -> Result<(), Box<dyn Error>> {
+            let mut selected = vec![names.is_empty(); self.num_tiles];
+            for name in names {
+                for (selected, allowed) in selected.iter_mut().zip(self.selected_tiles(name)?) {
+                    *selected |= allowed;
+                }
+            }
+            for (i, observed) in self.observed.iter().enumerate() {
+                if let Some(t) = *observed {
+                    if selected[t] {
+                        let (x, y) = self.coords(i);
+                        placed(x, y, t);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Collects the distinct tiles of the solved grid into a [`Palette`] along
+        /// with where each cell's tile is in it, unlike [`SimpleTiled::tile_atlas`]
+        /// which holds every variant.
+        pub fn palette(&self) -> Result<Palette, Box<dyn Error>> {
+            if self.unobserved() > 0 {
+                Err("Model is not fully rendered")?;
+            }
+            let mut used = vec![false; self.num_tiles];
+            for &t in self.observed.iter().flatten() {
+                used[t] = true;
+            }
+            let tiles: Vec<usize> = (0..self.num_tiles).filter(|&t| used[t]).collect();
+            let mut positions = vec![0; self.num_tiles];
+            let mut image = RgbaImage::new(
+                (tiles.len() * self.tile_width) as u32,
+                self.tile_height as u32,
+            );
+            for (position, &t) in tiles.iter().enumerate() {
+                positions[t] = position;
+                image.copy_from(&self.tiles[t].image, (position * self.tile_width) as u32, 0)?;
+            }
+            let indices = self
+                .lines(GridOrder::RowMajor)
+                .into_iter()
+                .map(|line| {
+                    line.into_iter()
+                        .map(|i| self.observed[i].map(|t| positions[t]))
+                        .collect()
+                })
+                .collect();
+            Ok(Palette {
+                image,
+                indices,
+                names: tiles.iter().map(|&t| self.tile_names[t].clone()).collect(),
+            })
+        }
+
+        /// Saves the output like [`Model::save`], but encoded as `format` whatever
+        /// the extension of `path`. `None` picks the format from the extension.
+        pub fn save_as(
+            &self,
+            path: &Path,
+            format: Option<ImageFormat>,
+        ) -> Result<(), Box<dyn Error>> {
+            let image = self.render()?;
+            match format {
+                Some(format) => image.save_with_format(path, format)?,
+                None => image.save(path)?,
+            }
+            Ok(())
+        }
+
+        /// Writes the output as an OpenRaster (`.ora`) file for editing in Krita or
+        /// GIMP, with every tile on its own layer above a background layer.
+        ///
+        /// Each layer holds all the cells with any variant of its tile and is
+        /// transparent elsewhere. Only the merged image has the edge blending.
+        #[cfg(feature = "ora")]
+        pub fn save_ora(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+            let merged = self.render()?;
+            let (width, height) = merged.dimensions();
+            let png = |image: &RgbaImage| -> Result<Vec<u8>, Box<dyn Error>> {
+                let mut bytes = std::io::Cursor::new(vec![]);
+                image.write_to(&mut bytes, image::ImageOutputFormat::Png)?;
+                Ok(bytes.into_inner())
+            };
+
+            let mut layers = vec![];
+            for base in &self.base_tiles {
+                let variants = base.first..base.first + base.cardinality;
+                let mut layer = RgbaImage::new(width, height);
+                let mut used = false;
+                for (i, t) in self.observed.iter().enumerate() {
+                    let Some(t) = t.filter(|t| variants.contains(t)) else {
+                        continue;
+                    };
+                    let (x, y) = self.coords(i);
+                    layer.copy_from(
+                        &self.tiles[t].image,
+                        (x * self.tile_width) as u32,
+                        (y * self.tile_height) as u32,
+                    )?;
+                    used = true;
+                }
+                if used {
+                    layers.push((base.name.as_str(), layer));
+                }
+            }
+            layers.push((
+                "background",
+                RgbaImage::from_pixel(width, height, Rgba(self.background)),
+            ));
+
+            let mut stack = format!(
+                "<?xml version='1.0' encoding='UTF-8'?>\n<image version=\"0.0.5\" w=\"{width}\" h=\"{height}\">\n<stack>\n"
+            );
+            let mut entries = vec![
+                ("mimetype".to_string(), b"image/openraster".to_vec()),
+                ("mergedimage.png".to_string(), png(&merged)?),
+                (
+                    "Thumbnails/thumbnail.png".to_string(),
+                    png(&imageops::thumbnail(
+                        &merged,
+                        width.min(256 * width / width.max(height)).max(1),
+                        height.min(256 * height / width.max(height)).max(1),
+                    ))?,
+                ),
+            ];
+            // The first layer of the stack is the topmost one
+            for (index, (name, layer)) in layers.iter().enumerate() {
+                let name = name
+                    .replace('&', "&amp;")
+                    .replace('"', "&quot;")
+                    .replace('<', "&lt;");
+                stack += &format!(
+                    "<layer name=\"{name}\" src=\"data/layer{index}.png\" x=\"0\" y=\"0\" opacity=\"1.0\" visibility=\"visible\"/>\n"
+                );
+                entries.push((format!("data/layer{index}.png"), png(layer)?));
+            }
+            stack += "</stack>\n</image>\n";
+            entries.insert(1, ("stack.xml".to_string(), stack.into_bytes()));
+            crate::write_stored_zip(path, &entries)
+        }
+
+        /// Writes [`SimpleTiled::observed_mapped`] as CSV, one row of the output
+        /// per line.
+        pub fn save_mapped_csv(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+            let rows = self
+                .observed_mapped()
+                .ok_or("Output isn't fully observed or the config has no [mapping]")?;
+            let mut csv = String::new();
+            for row in rows {
+                let fields: Vec<String> = row
+                    .iter()
+                    .map(|id| {
+                        let id = id.to_string();
+                        if id.contains([',', '"', '\n', '\r']) {
+                            format!("\"{}\"", id.replace('"', "\"\""))
+                        } else {
+                            id
+                        }
+                    })
+                    .collect();
+                csv += &fields.join(",");
+                csv += "\n";
+            }
+            std::fs::write(path, csv)?;
+            Ok(())
+        }
+
+        /// Writes [`SimpleTiled::flag_grid`] as CSV of `1` and `0`, one row of the
+        /// output per line.
+        pub fn save_flag_csv(&self, path: &Path, flag: &str) -> Result<(), Box<dyn Error>> {
+            let rows = self.flag_grid(flag).ok_or_else(|| {
+                format!("Output isn't fully observed or no tile has the flag '{flag}'")
+            })?;
+            let mut csv = String::new();
+            for row in rows {
+                let fields: Vec<&str> =
+                    row.iter().map(|&set| if set { "1" } else { "0" }).collect();
+                csv += &fields.join(",");
+                csv += "\n";
+            }
+            std::fs::write(path, csv)?;
+            Ok(())
+        }
+
+        /// Records every observation, ban and reset of the following runs, see
+        /// [`SimpleTiled::events`].
+        pub fn set_record_events(&mut self, record: bool) {
+            self.record_events = record;
+        }
+
+        /// The changes to the wave made by the most recent run in order, if
+        /// recording. A failed run's events end at its contradiction.
+        pub fn events(&self) -> &[SolveEvent] {
+            &self.events
+        }
+
+        /// Clears the model and applies `events`, as recorded by a model of the
+        /// same size and tileset, to the wave. Returns whether that leaves every
+        /// cell with a tile, which is then observed like at the end of a run.
+        ///
+        /// Only the wave is replayed, entropies and supports are left as cleared.
+        pub fn replay(&mut self, events: &[SolveEvent]) -> bool {
+            self.clear();
+            for event in events {
+                match event {
+                    SolveEvent::Observe { .. } => {}
+                    SolveEvent::Ban { cell, tile } => self.wave[*cell][*tile] = false,
+                    SolveEvent::Reset { cells } => {
+                        for &i in cells {
+                            self.wave[i].fill(true);
+                        }
+                    }
+                }
+            }
+            self.observe_remaining()
+        }
+
+        /// Statistics gathered during the most recent run.
+        pub fn stats(&self) -> &RunStats {
+            &self.stats
+        }
+
+        /// The tiles that can't appear anywhere in this grid, found by arc
+        /// consistency over the propagator without running the model.
+        ///
+        /// A tile is dropped once no generated cell is left where it has a
+        /// remaining tile to sit next to on every side. Sides past the edge of a
+        /// non-periodic grid or next to a cell the mask leaves out don't need
+        /// one, as propagation skips them too. This never drops a placeable
+        /// tile, but tiles it keeps can still fail to fit together.
+        pub fn unplaceable_tiles(&self) -> Vec<&str> {
+            // Which sides have a generated neighbor, for every kind of cell
+            let mut kinds: HashSet<[bool; 4]> = HashSet::new();
+            for i in (0..self.wave.len()).filter(|&i| self.cell_fits(i)) {
+                let mut sides = [false; 4];
+                for (direction, neighbor) in self.neighbors(i) {
+                    sides[direction.index()] = !self.masked(neighbor);
+                }
+                kinds.insert(sides);
+            }
+            let mut alive = vec![true; self.num_tiles];
+            let supported = |alive: &[bool], t: usize, d: Direction| {
+                self.propagator[d.index()][t].iter().any(|&t2| alive[t2])
+            };
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for t in 0..self.num_tiles {
+                    if !alive[t] {
+                        continue;
+                    }
+                    let placeable = kinds.iter().any(|sides| {
+                        Direction::ALL
+                            .into_iter()
+                            .all(|d| !sides[d.index()] || supported(&alive, t, d))
+                    });
+                    if !placeable {
+                        alive[t] = false;
+                        changed = true;
+                    }
+                }
+            }
+            (0..self.num_tiles)
+                .filter(|&t| !alive[t])
+                .map(|t| self.tile_names[t].as_str())
+                .collect()
+        }
+
+        /// Errors if no run can succeed because, by
+        /// [`SimpleTiled::unplaceable_tiles`], no tile or no variant of a required
+        /// tile can appear in this grid.
+        pub fn check_solvable(&self) -> Result<(), Box<dyn Error>> {
+            if !(0..self.wave.len()).any(|i| self.cell_fits(i)) {
+                // The mask leaves nothing to place
+                return Ok(());
+            }
+            let unplaceable = self.unplaceable_tiles();
+            if unplaceable.len() == self.num_tiles {
+                Err("No tile can ever appear in this grid, every tile lacks neighbors")?;
+            }
+            for (name, allowed) in &self.required_tiles {
+                if (0..self.num_tiles)
+                    .filter(|&t| allowed[t])
+                    .all(|t| unplaceable.contains(&self.tile_names[t].as_str()))
+                {
+                    Err(format!(
+                        "Required tile '{name}' can never appear in this grid"
+                    ))?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Runs the model `trials` times with seeds derived from `seed` and reports
+        /// how reliably the ruleset solves at this size.
+        ///
+        /// The model is left cleared afterwards.
+        pub fn benchmark_solvability(&mut self, trials: usize, seed: u64) -> SolvabilityReport {
+            let mut successes = 0;
+            let mut observations = 0;
+            let mut contradictions = 0;
+            for trial in 0..trials {
+                let trial_seed = derive_seed(seed, trial as u64);
+                if self.run_with_progress(trial_seed, usize::MAX, &ProgressBar::hidden()) {
+                    successes += 1;
+                }
+                observations += self.stats.observations;
+                contradictions += self.stats.contradictions;
+            }
+            self.clear();
+
+            let trials = trials.max(1) as f64;
+            SolvabilityReport {
+                success_rate: successes as f64 / trials,
+                avg_observations: observations as f64 / trials,
+                avg_contradictions: contradictions as f64 / trials,
+            }
+        }
+
+        /// Runs the model `trials` times with seeds derived from `seed` and renders
+        /// one pixel per cell for how often a failed run ended with that cell left
+        /// without a possible tile, from black for never to red for the most often.
+        ///
+        /// The model is left cleared afterwards.
+        pub fn failure_heatmap(&mut self, trials: usize, seed: u64) -> RgbaImage {
+            let mut failures = vec![0usize; self.wave.len()];
+            for trial in 0..trials {
+                let trial_seed = derive_seed(seed, trial as u64);
+                if !self.run_with_progress(trial_seed, usize::MAX, &ProgressBar::hidden()) {
+                    if let Some((x, y)) = self.stats.contradiction {
+                        failures[self.index(x, y)] += 1;
+                    }
+                }
+            }
+            self.clear();
+
+            let most = failures.iter().copied().max().unwrap_or(0).max(1);
+            RgbaImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+                let count = failures[self.index(x as usize, y as usize)];
+                let intensity = (count as f64 / most as f64 * 255.).round() as u8;
+                Rgba([intensity, 0, 0, 255])
+            })
+        }
+    }
+
+    impl Model for SimpleTiled {
+        fn run(&mut self, seed: u64, limit: usize) -> bool {
+            let bar = if self.progress {
+                let bar = ProgressBar::new(self.observed.len() as u64);
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] ({eta:>3}) [{pos:>7}/{len:7}] {msg}",
+                    )
+                    .unwrap(),
+                );
+                bar
+            } else {
+                ProgressBar::hidden()
+            };
+            let success = self.run_with_progress(seed, limit, &bar);
+            match self.stack_warning {
+                Some(threshold) if self.stats.peak_stack_depth > threshold => {
+                    self.warnings.push(format!(
+                        "propagation stack peaked at {} entries (threshold {threshold})",
+                        self.stats.peak_stack_depth
+                    ))
+                }
+                _ => (),
+            }
+            success
+        }
+
+        fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+            self.save_as(path, None)
+        }
+
+        fn render(&self) -> Result<RgbaImage, Box<dyn Error>> {
+            if self.unobserved() > 0 {
+                return Err("Model is not fully rendered")?;
+            }
+            let (image_width, image_height) =
+                output_dimensions(self.width, self.height, self.tile_width, self.tile_height)?;
+            let mut imgbuf =
+                ImageBuffer::from_pixel(image_width, image_height, Rgba(self.background));
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let Some(t) = self.observed[self.index(x, y)] else {
+                        continue;
+                    };
+                    imgbuf.copy_from(
+                        &self.tiles[t].image,
+                        (x * self.tile_width) as u32,
+                        (y * self.tile_height) as u32,
+                    )?;
+                }
+            }
+            if self.edge_blend > 0 {
+                self.feather_seams(&mut imgbuf);
+            }
+            Ok(imgbuf)
+        }
+
+        fn kind(&self) -> ModelKind {
+            ModelKind::SimpleTiled
+        }
+    }
+
+    /// Resolves a neighbor rule name (`"tile"`, `"tile 2"` or `"tile *"`) to the
+    /// indices of the tile variants it refers to.
+    ///
+    /// A `*` orientation expands to every variant of the tile. Unknown tiles and
+    /// orientations beyond the tile's cardinality are errors.
+    fn neighbor_variants(
+        name: &str,
+        action: &[[i32; 8]],
+        first_occurence: &HashMap<String, usize>,
+        cardinalities: &HashMap<String, usize>,
+    ) -> Result<Vec<usize>, Box<dyn Error>> {
+        let tile_name: Vec<&str> = name.split(' ').collect();
+        let (Some(&first), Some(&cardinality)) = (
+            first_occurence.get(tile_name[0]),
+            cardinalities.get(tile_name[0]),
+        ) else {
+            return Err(format!(
+                "neighbor references unknown tile or group '{}'",
+                tile_name[0]
+            ))?;
+        };
+        match tile_name.get(1) {
+            Some(&"*") => Ok((first..first + cardinality).collect()),
+            Some(orientation) => {
+                let orientation = orientation.parse::<usize>()?;
+                if orientation >= cardinality {
+                    Err(format!(
+                        "neighbor references orientation {orientation} of '{}' which only has {cardinality} variants",
+                        tile_name[0]
+                    ))?;
+                }
+                Ok(vec![action[first][orientation].try_into()?])
+            }
+            None => Ok(vec![action[first][0].try_into()?]),
+        }
+    }
+
+    /// The pixels along each side of `tile`, indexed by [`Direction::index`] and
+    /// running left to right or top to bottom.
+    fn tile_edges(tile: &TileObject) -> [Vec<Rgba<u8>>; 4] {
+        let image = tile.image.to_rgba8();
+        let (width, height) = image.dimensions();
+        let column = |x: u32| (0..height).map(|y| *image.get_pixel(x, y)).collect();
+        let row = |y: u32| (0..width).map(|x| *image.get_pixel(x, y)).collect();
+        let mut edges: [Vec<Rgba<u8>>; 4] = Default::default();
+        for direction in Direction::ALL {
+            edges[direction.index()] = match direction {
+                Direction::Left => column(0),
+                Direction::Right => column(width - 1),
+                Direction::Up => row(0),
+                Direction::Down => row(height - 1),
+            };
+        }
+        edges
+    }
+
+    /// The `(direction, t1, t2)` entries of the dense propagator set by one rule.
+    type NeighborRule = Vec<(usize, usize, usize)>;
+
+    /// The entries of the dense propagator each of `neighbors` sets.
+    fn neighbor_rules(
+        neighbors: &[Neighbor],
+        groups: &HashMap<String, Vec<String>>,
+        action: &[[i32; 8]],
+        first_occurence: &HashMap<String, usize>,
+        cardinalities: &HashMap<String, usize>,
+        auto_symmetry: bool,
+    ) -> Result<Vec<NeighborRule>, Box<dyn Error>> {
+        let (horizontal, vertical) = (Direction::Left.index(), Direction::Down.index());
+        let act = |t: usize, k: usize| action[t][k] as usize;
+
+        let mut group_variants = HashMap::with_capacity(groups.len());
+        for (group, members) in groups {
+            if cardinalities.contains_key(group) {
+                Err(format!("[groups] name '{group}' is also a tile"))?;
+            }
+            let mut variants = vec![];
+            for member in members {
+                variants.extend(
+                    neighbor_variants(member, action, first_occurence, cardinalities)
+                        .map_err(|err| format!("[groups] {group}: {err}"))?,
+                );
+            }
+            variants.sort_unstable();
+            variants.dedup();
+            group_variants.insert(group.as_str(), variants);
+        }
+        let variants = |name: &str| match group_variants.get(name) {
+            Some(variants) => Ok(variants.clone()),
+            None => neighbor_variants(name, action, first_occurence, cardinalities),
+        };
+
+        let mut rules = Vec::with_capacity(neighbors.len());
+        for neighbor in neighbors {
+            let lefts = variants(&neighbor.left)?;
+            let rights = variants(&neighbor.right)?;
+            let pairs = lefts
+                .iter()
+                .flat_map(|&left| rights.iter().map(move |&right| (left, right)));
+            let mut rule = vec![];
+            if !auto_symmetry {
+                // The rule as written, `left` and `right` being top and bottom
+                // for vertical rules
+                rule.extend(pairs.map(|(left, right)| {
+                    if neighbor.vertical {
+                        (vertical, left, right)
+                    } else {
+                        (horizontal, right, left)
+                    }
+                }));
+                rules.push(rule);
+                continue;
+            }
+            if neighbor.vertical {
+                Err(format!(
+                    "neighbor {{ left=\"{}\", right=\"{}\" }} is vertical, which needs auto_symmetry_neighbors = false",
+                    neighbor.left, neighbor.right
+                ))?;
+            }
+            for (left, right) in pairs {
+                let down = act(left, 1);
+                let up = act(right, 1);
+
+                rule.extend([
+                    (horizontal, right, left),
+                    (horizontal, act(right, 6), act(left, 6)),
+                    (horizontal, act(left, 4), act(right, 4)),
+                    (horizontal, act(left, 2), act(right, 2)),
+                    (vertical, up, down),
+                    (vertical, act(down, 6), act(up, 6)),
+                    (vertical, act(up, 4), act(down, 4)),
+                    (vertical, act(down, 2), act(up, 2)),
+                ]);
+            }
+            rules.push(rule);
+        }
+        Ok(rules)
+    }
+
+    /// Finds the neighbor rules, given as the propagator entries each one sets,
+    /// that only set entries earlier rules already did, e.g. a repeated rule or
+    /// the mirror image of one. Each comes with the earlier rules it repeats.
+    fn redundant_neighbors(rules: &[NeighborRule]) -> Vec<(usize, Vec<usize>)> {
+        let mut first_rule = HashMap::new();
+        let mut redundant = vec![];
+        for (index, rule) in rules.iter().enumerate() {
+            if rule.iter().all(|entry| first_rule.contains_key(entry)) {
+                let mut earlier: Vec<usize> = rule.iter().map(|entry| first_rule[entry]).collect();
+                earlier.sort_unstable();
+                earlier.dedup();
+                redundant.push((index, earlier));
+            }
+            for &entry in rule {
+                first_rule.entry(entry).or_insert(index);
+            }
+        }
+        redundant
+    }
+
+    const GLYPH_HEIGHT: u32 = 5;
+
+    /// The rows of a 3x5 pixel glyph for `c`, most significant of the three bits
+    /// on the left. Letters are drawn in capitals and unknown characters as `?`.
+    fn glyph(c: char) -> [u8; 5] {
+        match c.to_ascii_lowercase() {
+            '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+            '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+            '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+            '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+            '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+            '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+            '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+            '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+            '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+            '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+            'a' => [0b010, 0b101, 0b111, 0b101, 0b101],
+            'b' => [0b110, 0b101, 0b110, 0b101, 0b110],
+            'c' => [0b011, 0b100, 0b100, 0b100, 0b011],
+            'd' => [0b110, 0b101, 0b101, 0b101, 0b110],
+            'e' => [0b111, 0b100, 0b110, 0b100, 0b111],
+            'f' => [0b111, 0b100, 0b110, 0b100, 0b100],
+            'g' => [0b011, 0b100, 0b101, 0b101, 0b011],
+            'h' => [0b101, 0b101, 0b111, 0b101, 0b101],
+            'i' => [0b111, 0b010, 0b010, 0b010, 0b111],
+            'j' => [0b001, 0b001, 0b001, 0b101, 0b010],
+            'k' => [0b101, 0b101, 0b110, 0b101, 0b101],
+            'l' => [0b100, 0b100, 0b100, 0b100, 0b111],
+            'm' => [0b101, 0b111, 0b111, 0b101, 0b101],
+            'n' => [0b110, 0b101, 0b101, 0b101, 0b101],
+            'o' => [0b010, 0b101, 0b101, 0b101, 0b010],
+            'p' => [0b110, 0b101, 0b110, 0b100, 0b100],
+            'q' => [0b010, 0b101, 0b101, 0b111, 0b011],
+            'r' => [0b110, 0b101, 0b110, 0b101, 0b101],
+            's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+            't' => [0b111, 0b010, 0b010, 0b010, 0b010],
+            'u' => [0b101, 0b101, 0b101, 0b101, 0b111],
+            'v' => [0b101, 0b101, 0b101, 0b101, 0b010],
+            'w' => [0b101, 0b101, 0b111, 0b111, 0b101],
+            'x' => [0b101, 0b101, 0b010, 0b101, 0b101],
+            'y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+            'z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+            ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+            ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+            '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+            '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+            '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+            _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+        }
+    }
+
+    /// The width in pixels of `text` drawn by [`draw_text`].
+    fn text_width(text: &str) -> u32 {
+        (text.chars().count() as u32 * 4).saturating_sub(1)
+    }
+
+    /// Draws `text` with its top-left corner at `(x, y)`, clipped to the image.
+    fn draw_text(image: &mut RgbaImage, x: u32, y: u32, text: &str, color: Rgba<u8>) {
+        for (k, c) in text.chars().enumerate() {
+            for (row, bits) in glyph(c).into_iter().enumerate() {
+                for column in 0..3 {
+                    let px = x + k as u32 * 4 + column;
+                    let py = y + row as u32;
+                    if bits & (0b100 >> column) != 0 && px < image.width() && py < image.height() {
+                        image.put_pixel(px, py, color);
+                    }
+                }
+            }
+        }
+    }
+
+    fn output_dimensions(
+        width: usize,
+        height: usize,
+        tile_width: usize,
+        tile_height: usize,
+    ) -> Result<(u32, u32), String> {
+        let to_pixels = |cells: usize, tile_size: usize| {
+            cells
+                .checked_mul(tile_size)
+                .and_then(|pixels| u32::try_from(pixels).ok())
+        };
+        match (to_pixels(width, tile_width), to_pixels(height, tile_height)) {
+            (Some(image_width), Some(image_height)) => Ok((image_width, image_height)),
+            _ => Err(format!(
+                "Output of {width}x{height} tiles at {tile_width}x{tile_height}px per tile exceeds the maximum image size of {}x{} pixels",
+                u32::MAX,
+                u32::MAX
+            )),
+        }
+    }
+
+    impl Display for SimpleTiled {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            if f.alternate() {
+                f.write_str(&self.format_compact(GridOrder::RowMajor))
+            } else {
+                f.write_str(&self.format_grid(GridOrder::RowMajor))
+            }
+        }
+    }
+
+    /// The overlapping model, which learns which `n`×`n` patterns may overlap
+    /// from a sample image instead of from adjacency rules.
+    pub mod overlapping {
+        use std::{collections::HashMap, error::Error, path::Path};
+
+        use image::{DynamicImage, Rgba, RgbaImage};
+
+        use super::{Direction, Heuristic, Model, ModelKind, SimpleTiled, Tileset};
+        use crate::tile::{BaseTile, Symmetry, TileObject};
+
+        /// Generates images whose every `n`×`n` window also appears in a sample.
+        ///
+        /// Each pattern of the sample is a tile of a [`SimpleTiled`] model that
+        /// does the solving, with patterns allowed next to each other where they
+        /// agree on their overlap. A cell stands for the pattern whose top left
+        /// corner it is, so in non-periodic outputs the last `n - 1` rows and
+        /// columns come from the patterns before them.
+        #[derive(Debug)]
+        pub struct OverlappingModel {
+            model: SimpleTiled,
+            n: usize,
+            colors: Vec<Rgba<u8>>,
+            // The color indices of every pattern, row by row
+            patterns: Vec<Vec<usize>>,
+        }
+
+        impl OverlappingModel {
+            /// Learns the `n`×`n` patterns of `sample`, which wraps around at its
+            /// edges, weighted by how often they occur. `symmetry` from 1 to 8 adds
+            /// that many of each pattern's reflections and rotations, in the order
+            /// of the reference implementation.
+            pub fn new(
+                sample: &DynamicImage,
+                n: usize,
+                symmetry: usize,
+                width: usize,
+                height: usize,
+                periodic: bool,
+                heuristic: Heuristic,
+            ) -> Result<Self, Box<dyn Error>> {
+                if n == 0 || !(1..=8).contains(&symmetry) {
+                    Err("Pattern size must be positive and symmetry between 1 and 8")?;
+                }
+                if !periodic && (width < n || height < n) {
+                    Err(format!(
+                        "Output of {width}x{height} is smaller than the {n}x{n} patterns"
+                    ))?;
+                }
+                let sample = sample.to_rgba8();
+                let (sample_width, sample_height) =
+                    (sample.width() as usize, sample.height() as usize);
+                if sample_width == 0 || sample_height == 0 {
+                    Err("Sample image is empty")?;
+                }
+                let mut colors: Vec<Rgba<u8>> = vec![];
+                let mut pixels = Vec::with_capacity(sample_width * sample_height);
+                for &pixel in sample.pixels() {
+                    let color = colors.iter().position(|&c| c == pixel).unwrap_or_else(|| {
+                        colors.push(pixel);
+                        colors.len() - 1
+                    });
+                    pixels.push(color);
+                }
+
+                let pattern = |f: &dyn Fn(usize, usize) -> usize| -> Vec<usize> {
+                    (0..n * n).map(|i| f(i % n, i / n)).collect()
+                };
+                let mut patterns: Vec<Vec<usize>> = vec![];
+                let mut weights: Vec<f64> = vec![];
+                let mut known: HashMap<Vec<usize>, usize> = HashMap::new();
+                for y in 0..sample_height {
+                    for x in 0..sample_width {
+                        let mut variants = Vec::with_capacity(8);
+                        variants.push(pattern(&|dx, dy| {
+                            pixels
+                                [(x + dx) % sample_width + (y + dy) % sample_height * sample_width]
+                        }));
+                        while variants.len() < symmetry {
+                            let last = &variants[variants.len() - 1];
+                            // Odd variants reflect the one before, even ones rotate
+                            // the one two before
+                            let next = if variants.len() % 2 == 1 {
+                                pattern(&|x, y| last[n - 1 - x + y * n])
+                            } else {
+                                let base = &variants[variants.len() - 2];
+                                pattern(&|x, y| base[n - 1 - y + x * n])
+                            };
+                            variants.push(next);
+                        }
+                        for variant in variants {
+                            match known.get(&variant) {
+                                Some(&p) => weights[p] += 1.,
+                                None => {
+                                    known.insert(variant.clone(), patterns.len());
+                                    patterns.push(variant);
+                                    weights.push(1.);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let agrees = |p1: &[usize], p2: &[usize], dx: isize, dy: isize| {
+                    let n = n as isize;
+                    let (xmin, xmax) = if dx < 0 { (0, dx + n) } else { (dx, n) };
+                    let (ymin, ymax) = if dy < 0 { (0, dy + n) } else { (dy, n) };
+                    (ymin..ymax).all(|y| {
+                        (xmin..xmax).all(|x| {
+                            p1[(x + n * y) as usize] == p2[(x - dx + n * (y - dy)) as usize]
+                        })
+                    })
+                };
+                let mut propagator = vec![vec![vec![]; patterns.len()]; 4];
+                for direction in Direction::ALL {
+                    let (dx, dy) = direction.delta();
+                    for (p1, allowed) in propagator[direction.index()].iter_mut().enumerate() {
+                        *allowed = (0..patterns.len())
+                            .filter(|&p2| agrees(&patterns[p1], &patterns[p2], dx, dy))
+                            .collect();
+                    }
+                }
+
+                let tileset = Tileset {
+                    tiles: patterns
+                        .iter()
+                        .zip(&weights)
+                        .map(|(pattern, &weight)| TileObject {
+                            image: DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                                1,
+                                1,
+                                colors[pattern[0]],
+                            )),
+                            weight,
+                        })
+                        .collect(),
+                    base_tiles: (0..patterns.len())
+                        .map(|p| BaseTile {
+                            name: format!("pattern{p}"),
+                            symmetry: Symmetry::X,
+                            first: p,
+                            cardinality: 1,
+                        })
+                        .collect(),
+                    tile_names: (0..patterns.len())
+                        .map(|p| format!("pattern{p} 0"))
+                        .collect(),
+                    mapped_ids: None,
+                    tile_flags: vec![HashMap::new(); patterns.len()],
+                    propagator,
+                    action: (0..patterns.len()).map(|p| [p; 8]).collect(),
+                };
+                let mut model =
+                    SimpleTiled::from_tileset(tileset, width, height, periodic, heuristic);
+                model.n = n;
+                Ok(Self {
+                    model,
+                    n,
+                    colors,
+                    patterns,
+                })
+            }
+
+            /// How many distinct patterns the sample holds.
+            pub fn pattern_count(&self) -> usize {
+                self.patterns.len()
+            }
+
+            /// The solving model, e.g. to change its settings.
+            pub fn model_mut(&mut self) -> &mut SimpleTiled {
+                &mut self.model
+            }
+        }
+
+        impl Model for OverlappingModel {
+            fn run(&mut self, seed: u64, limit: usize) -> bool {
+                self.model.run(seed, limit)
+            }
+
+            fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+                Ok(self.render()?.save(path)?)
+            }
+
+            /// Renders one pixel per cell from the observed patterns.
+            fn render(&self) -> Result<RgbaImage, Box<dyn Error>> {
+                let model = &self.model;
+                if model.unobserved() > 0 {
+                    Err("Model is not fully rendered")?;
+                }
+                // Cells past the last anchor take their pixel from the pattern of
+                // that anchor, at the same offset
+                let mut image = RgbaImage::new(model.width as u32, model.height as u32);
+                for (x, y, pixel) in image.enumerate_pixels_mut() {
+                    let (anchor, dx, dy) = model.pattern_anchor(x as usize, y as usize);
+                    if let Some(p) = model.observed[anchor] {
+                        *pixel = self.colors[self.patterns[p][dx + dy * self.n]];
+                    }
+                }
+                Ok(image)
+            }
+
+            fn kind(&self) -> ModelKind {
+                ModelKind::Overlapping
+            }
+        }
+    }
+
+    pub mod blocks {
+        use std::{collections::HashMap, error::Error, path::Path};
+
+        use image::{GenericImage, RgbaImage};
+
+        use super::{Direction, Heuristic, Model, ModelKind, SimpleTiled, Tileset};
+        use crate::{
+            tile::{BaseTile, Symmetry, TileObject},
+            Config,
+        };
+
+        /// More blocks than this fitting the rules is an error, as the propagator
+        /// grows with their square.
+        const MAX_BLOCKS: usize = 20_000;
+
+        /// Places `n`×`n` blocks of tiles as atomic patterns, see
+        /// [`BlockModel::new`].
+        ///
+        /// Every block whose tiles follow the config's neighbor rules is a tile
+        /// of a [`SimpleTiled`] model that does the solving, with blocks allowed
+        /// next to each other where they agree on their overlap. A cell stands
+        /// for the block whose top left corner it is. On periodic grids every
+        /// cell is such an anchor and blocks wrap around the edges. Otherwise
+        /// only cells with `x + n <= width` and `y + n <= height` are observed and
+        /// the last `n - 1` rows and columns take their tiles from the blocks of
+        /// the anchors before them.
+        #[derive(Debug)]
+        pub struct BlockModel {
+            model: SimpleTiled,
+            n: usize,
+            tiles: Vec<TileObject>,
+            tile_names: Vec<String>,
+            // The tile indices of every block, row by row
+            blocks: Vec<Vec<usize>>,
+        }
+
+        impl BlockModel {
+            /// Loads the tiles of `config` from `folder` like [`SimpleTiled::new`]
+            /// and gathers every `n`×`n` block of them its neighbor rules allow,
+            /// weighted by the product of the weights of their tiles.
+            pub fn new(
+                config: Config,
+                folder: &str,
+                n: usize,
+                width: usize,
+                height: usize,
+                periodic: bool,
+                heuristic: Heuristic,
+            ) -> Result<Self, Box<dyn Error>> {
+                if n == 0 {
+                    Err("Block size must be positive")?;
+                }
+                if !periodic && (width < n || height < n) {
+                    Err(format!(
+                        "Output of {width}x{height} is smaller than the {n}x{n} blocks"
+                    ))?;
+                }
+                let mut tileset = SimpleTiled::new(config, folder, 1, 1, false, heuristic.clone())?;
+                let allowed = |direction: Direction, t1: usize, t2: usize| {
+                    tileset.propagator[direction.index()][t1].contains(&t2)
+                };
+
+                // Fills the blocks row by row, checking each tile against the one
+                // on its left and the one above
+                let mut blocks: Vec<Vec<usize>> = vec![];
+                let mut block = Vec::with_capacity(n * n);
+                let mut next = vec![0];
+                while let Some(t) = next.pop() {
+                    block.truncate(next.len());
+                    if t == tileset.num_tiles {
+                        continue;
+                    }
+                    next.push(t + 1);
+                    let at = block.len();
+                    let fits = (at % n == 0 || allowed(Direction::Right, block[at - 1], t))
+                        && (at < n || allowed(Direction::Down, block[at - n], t));
+                    if !fits {
+                        continue;
+                    }
+                    block.push(t);
+                    if block.len() < n * n {
+                        next.push(0);
+                    } else {
+                        if blocks.len() == MAX_BLOCKS {
+                            Err(format!(
+                                "More than {MAX_BLOCKS} {n}x{n} blocks fit the rules, try a smaller size"
+                            ))?;
+                        }
+                        blocks.push(block.clone());
+                    }
+                }
+                if blocks.is_empty() {
+                    Err(format!("No {n}x{n} block of tiles fits the rules"))?;
+                }
+
+                // A block goes next to another where their overlap matches, and
+                // for 1x1 blocks, which don't overlap, where the rules allow it
+                let overlap = |block: &[usize], (xs, ys): (isize, isize)| -> Vec<usize> {
+                    let n = n as isize;
+                    let range = |shift: isize| shift.max(0)..(n + shift).min(n);
+                    range(ys)
+                        .flat_map(|y| range(xs).map(move |x| (x, y)))
+                        .map(|(x, y)| block[(x + y * n) as usize])
+                        .collect()
+                };
+                let mut propagator = vec![vec![vec![]; blocks.len()]; 4];
+                for direction in Direction::ALL {
+                    let (dx, dy) = direction.delta();
+                    let mut by_overlap: HashMap<Vec<usize>, Vec<usize>> = HashMap::new();
+                    for (b, block) in blocks.iter().enumerate() {
+                        by_overlap
+                            .entry(overlap(block, (-dx, -dy)))
+                            .or_default()
+                            .push(b);
+                    }
+                    for (b1, block) in blocks.iter().enumerate() {
+                        let Some(candidates) = by_overlap.get(&overlap(block, (dx, dy))) else {
+                            continue;
+                        };
+                        propagator[direction.index()][b1] = candidates
+                            .iter()
+                            .copied()
+                            .filter(|&b2| allowed(direction, block[0], blocks[b2][0]))
+                            .collect();
+                    }
+                }
+
+                let tiles = std::mem::take(&mut tileset.tiles);
+                let inner = Tileset {
+                    tiles: blocks
+                        .iter()
+                        .map(|block| TileObject {
+                            image: tiles[block[0]].image.clone(),
+                            weight: block.iter().map(|&t| tiles[t].weight).product(),
+                        })
+                        .collect(),
+                    base_tiles: (0..blocks.len())
+                        .map(|b| BaseTile {
+                            name: format!("block{b}"),
+                            symmetry: Symmetry::X,
+                            first: b,
+                            cardinality: 1,
+                        })
+                        .collect(),
+                    tile_names: (0..blocks.len()).map(|b| format!("block{b} 0")).collect(),
+                    mapped_ids: None,
+                    tile_flags: vec![HashMap::new(); blocks.len()],
+                    propagator,
+                    action: (0..blocks.len()).map(|b| [b; 8]).collect(),
+                };
+                let mut model =
+                    SimpleTiled::from_tileset(inner, width, height, periodic, heuristic);
+                model.n = n;
+                model.warnings = tileset.take_warnings();
+                Ok(Self {
+                    model,
+                    n,
+                    tiles,
+                    tile_names: tileset.tile_names,
+                    blocks,
+                })
+            }
+
+            /// How many blocks fit the rules.
+            pub fn block_count(&self) -> usize {
+                self.blocks.len()
+            }
+
+            /// The name of the tile in every cell, e.g. `corner 3`, row by row from
+            /// the top, `None` for cells whose block isn't observed.
+            pub fn observed_tiles(&self) -> Vec<Option<&str>> {
+                let model = &self.model;
+                (0..model.height)
+                    .flat_map(|y| (0..model.width).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        let (anchor, dx, dy) = model.pattern_anchor(x, y);
+                        let b = model.observed[anchor]?;
+                        Some(self.tile_names[self.blocks[b][dx + dy * self.n]].as_str())
+                    })
+                    .collect()
+            }
+
+            /// The solving model, e.g. to change its settings.
+            pub fn model_mut(&mut self) -> &mut SimpleTiled {
+                &mut self.model
+            }
+        }
+
+        impl Model for BlockModel {
+            fn run(&mut self, seed: u64, limit: usize) -> bool {
+                self.model.run(seed, limit)
+            }
+
+            fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+                Ok(self.render()?.save(path)?)
+            }
+
+            /// Copies the tiles of every observed block into the output, the
+            /// blocks of the last anchors in full.
+            fn render(&self) -> Result<RgbaImage, Box<dyn Error>> {
+                let model = &self.model;
+                if model.unobserved() > 0 {
+                    Err("Model is not fully rendered")?;
+                }
+                let (tile_width, tile_height) = (model.tile_width as u32, model.tile_height as u32);
+                let mut image = RgbaImage::new(
+                    model.width as u32 * tile_width,
+                    model.height as u32 * tile_height,
+                );
+                for y in 0..model.height {
+                    for x in 0..model.width {
+                        let (anchor, dx, dy) = model.pattern_anchor(x, y);
+                        let Some(b) = model.observed[anchor] else {
+                            continue;
+                        };
+                        let t = self.blocks[b][dx + dy * self.n];
+                        image.copy_from(
+                            &self.tiles[t].image.to_rgba8(),
+                            x as u32 * tile_width,
+                            y as u32 * tile_height,
+                        )?;
+                    }
+                }
+                Ok(image)
+            }
+
+            fn kind(&self) -> ModelKind {
+                ModelKind::Blocks
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::Tile;
+
+        const KNOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/knot");
+
+        fn knot_model(config: &str, width: usize, height: usize) -> SimpleTiled {
+            let config: Config = toml::from_str(config).unwrap();
+            SimpleTiled::new(config, KNOT, width, height, false, Heuristic::Entropy).unwrap()
+        }
+
+        #[test]
+        fn wildcard_neighbor_expands_to_every_variant() {
+            let tiles = r#"
+                tiles = [
+                    { name="corner.png", symmetry="L" },
+                    { name="empty.png", symmetry="X" },
+                ]
+            "#;
+            let wildcard = knot_model(
+                &format!(r#"{tiles} neighbors = [ {{ left="corner *", right="empty" }} ]"#),
+                2,
+                2,
+            );
+            let explicit = knot_model(
+                &format!(
+                    r#"{tiles} neighbors = [
+                        {{ left="corner", right="empty" }},
+                        {{ left="corner 1", right="empty" }},
+                        {{ left="corner 2", right="empty" }},
+                        {{ left="corner 3", right="empty" }},
+                    ]"#
+                ),
+                2,
+                2,
+            );
+            assert_eq!(wildcard.propagator, explicit.propagator);
+        }
+
+        #[test]
+        fn group_neighbor_expands_to_every_member() {
+            let tiles = r#"
+                tiles = [
+                    { name="corner.png", symmetry="L" },
+                    { name="empty.png", symmetry="X" },
+                    { name="line.png", symmetry="I" },
+                ]
+            "#;
+            let grouped = knot_model(
+                &format!(
+                    r#"{tiles} neighbors = [ {{ left="edges", right="empty" }} ]
+                    [groups]
+                    edges = ["corner 1", "line *"]"#
+                ),
+                2,
+                2,
+            );
+            let explicit = knot_model(
+                &format!(
+                    r#"{tiles} neighbors = [
+                        {{ left="corner 1", right="empty" }},
+                        {{ left="line", right="empty" }},
+                        {{ left="line 1", right="empty" }},
+                    ]"#
+                ),
+                2,
+                2,
+            );
+            assert_eq!(grouped.propagator, explicit.propagator);
+
+            for groups in [r#"edges = ["crate"]"#, r#"line = ["corner"]"#] {
+                let config = format!(
+                    r#"{tiles} neighbors = [ {{ left="corner", right="empty" }} ]
+                    [groups]
+                    {groups}"#
+                );
+                let config: Config = toml::from_str(&config).unwrap();
+                assert!(SimpleTiled::new(config, KNOT, 2, 2, false, Heuristic::Entropy).is_err());
+            }
+        }
+
+        #[test]
+        fn benchmark_solvability_reports_rates() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 6, 6);
+            let report = model.benchmark_solvability(4, 7);
+            assert!((0.0..=1.0).contains(&report.success_rate));
+            assert!(report.avg_observations > 0.0);
+            assert_eq!(report, model.benchmark_solvability(4, 7));
+        }
+
+        #[test]
+        fn constrained_edge_matches_neighbor() {
+            let config = include_str!("../knot/config.toml");
+            let mut left = knot_model(config, 4, 5);
+            assert!((0..100).any(|seed| left.run(seed, usize::MAX)));
+
+            let mut right = knot_model(config, 3, 5);
+            right.constrain_edge_from(&left, Direction::Left).unwrap();
+            assert!((0..100).any(|seed| right.run(seed, usize::MAX)));
+            for y in 0..5 {
+                let left_tile = left.observed[3 + y * 4].unwrap();
+                let right_tile = right.observed[y * 3].unwrap();
+                assert!(right.propagator[Direction::Right.index()][left_tile].contains(&right_tile));
+            }
+
+            let mut short = knot_model(config, 3, 4);
+            assert!(short.constrain_edge_from(&left, Direction::Left).is_err());
+        }
+
+        #[test]
+        fn z_symmetry_expands_to_rotations_and_mirrors() {
+            let model = knot_model(
+                r#"
+                tiles = [ { name="corner.png", symmetry="Z" } ]
+                neighbors = [ { left="corner", right="corner" } ]
+                "#,
+                2,
+                2,
+            );
+            assert_eq!(model.tiles.len(), 4);
+            assert_eq!(model.tiles[1].image, model.tiles[0].image.rotate270());
+            assert_eq!(model.tiles[2].image, model.tiles[0].image.fliph());
+            assert_eq!(model.tiles[3].image, model.tiles[2].image.rotate270());
+
+            let left = Direction::Left.index();
+            let down = Direction::Down.index();
+            // The rule itself and its mirror image horizontally...
+            assert_eq!(model.propagator[left][0], vec![0]);
+            assert_eq!(model.propagator[left][2], vec![2]);
+            // ...and both rotated a quarter turn vertically
+            assert_eq!(model.propagator[down][1], vec![1]);
+            assert_eq!(model.propagator[down][3], vec![3]);
+            assert!(model.propagator[left][1].is_empty());
+        }
+
+        #[test]
+        fn f_variants_are_exact_dihedral_transforms() {
+            let mut source = RgbaImage::new(3, 3);
+            for (i, pixel) in source.pixels_mut().enumerate() {
+                *pixel = Rgba([i as u8, 0, 0, 255]);
+            }
+            let tile = TileObject {
+                image: DynamicImage::ImageRgba8(source),
+                weight: 1.,
+            };
+            // A quarter turn counterclockwise and a horizontal flip, as pixel maps
+            let turn =
+                |image: &RgbaImage| RgbaImage::from_fn(3, 3, |x, y| *image.get_pixel(2 - y, x));
+            let flip =
+                |image: &RgbaImage| RgbaImage::from_fn(3, 3, |x, y| *image.get_pixel(2 - x, y));
+            let mut expected = vec![tile.image.to_rgba8()];
+            for i in 1..4 {
+                expected.push(turn(&expected[i - 1]));
+            }
+            for i in 0..4 {
+                expected.push(flip(&expected[i]));
+            }
+
+            let variants = tile.variants(Symmetry::F);
+            assert_eq!(variants.len(), 8);
+            for (variant, expected) in variants.iter().zip(&expected) {
+                assert_eq!(&variant.image.to_rgba8(), expected);
+            }
+            let mut transposed = tile.clone();
+            transposed.transpose();
+            assert_eq!(
+                transposed.image.to_rgba8(),
+                RgbaImage::from_fn(3, 3, |x, y| *expected[0].get_pixel(y, x))
+            );
+        }
+
+        #[test]
+        fn entropy_map_darkens_as_cells_collapse() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 3, 2);
+            model.clear();
+            let map = model.render_entropy_map();
+            assert_eq!(map.dimensions(), (3, 2));
+            assert!(map.pixels().all(|p| *p == Rgba([255, 255, 255, 255])));
+
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let map = model.render_entropy_map();
+            assert!(map.pixels().all(|p| *p == Rgba([0, 0, 0, 255])));
+        }
+
+        #[test]
+        fn tile_globs_expand_to_matching_files() {
+            let mut config: Config = toml::from_str(
+                r#"
+                tiles = [
+                    { name="c*.png", symmetry="L", weight=2.0 },
+                    { name="empty.png", symmetry="X" },
+                ]
+                neighbors = [ { left="corner", right="cross" } ]
+                "#,
+            )
+            .unwrap();
+            config.expand_tile_globs(KNOT).unwrap();
+            let names: Vec<_> = config.tiles.iter().map(|t| t.name.as_str()).collect();
+            assert_eq!(names, ["corner.png", "cross.png", "empty.png"]);
+            assert_eq!(config.tiles[1].symmetry.as_deref(), Some("L"));
+            assert_eq!(config.tiles[1].weight, Some(2.0));
+
+            config.tiles[0].name = "missing*.png".to_string();
+            assert!(config.expand_tile_globs(KNOT).is_err());
+        }
+
+        #[test]
+        fn try_seed_leaves_model_cleared() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 4, 4);
+            let seed = (0..100)
+                .find(|&seed| model.try_seed(seed, usize::MAX))
+                .unwrap();
+            assert!(model.observed.iter().all(Option::is_none));
+            assert!(model
+                .sums_of_ones
+                .iter()
+                .all(|&s| s as usize == model.num_tiles));
+            assert!(model.save(Path::new("unused.png")).is_err());
+
+            assert!(model.run(seed, usize::MAX));
+            assert!(model.observed.iter().all(Option::is_some));
+        }
+
+        #[test]
+        fn reskin_keeps_layout_and_rederives_variants() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 4, 4);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let observed = model.observed.clone();
+
+            let mut night = model.tiles[0].image.clone();
+            night.invert();
+            model
+                .reskin(HashMap::from([("corner".to_string(), night.clone())]))
+                .unwrap();
+            assert_eq!(model.observed, observed);
+            assert_eq!(model.tiles[0].image, night);
+            assert_eq!(model.tiles[1].image, night.rotate270());
+
+            let unknown = HashMap::from([("lava".to_string(), night.clone())]);
+            assert!(model.reskin(unknown).is_err());
+            let wrong_size = HashMap::from([(
+                "corner".to_string(),
+                night.resize_exact(3, 3, image::imageops::Nearest),
+            )]);
+            assert!(model.reskin(wrong_size).is_err());
+        }
+
+        #[test]
+        fn decorations_only_cover_matching_tiles() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 6, 6);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let plain = model.render().unwrap();
+
+            let flower =
+                DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([255, 0, 255, 255])));
+            let decorations = HashMap::from([("empty".to_string(), (flower, 1.0))]);
+            let decorated = model.render_decorated(&decorations, 3).unwrap();
+            let empty = model
+                .base_tiles
+                .iter()
+                .find(|b| b.name == "empty")
+                .unwrap()
+                .first;
+            for y in 0..6 {
+                for x in 0..6 {
+                    let center = decorated.get_pixel(x as u32 * 10 + 5, y as u32 * 10 + 5);
+                    let original = plain.get_pixel(x as u32 * 10 + 5, y as u32 * 10 + 5);
+                    if model.observed[x + y * 6] == Some(empty) {
+                        assert_eq!(*center, Rgba([255, 0, 255, 255]));
+                    } else {
+                        assert_eq!(center, original);
+                    }
+                }
+            }
+
+            let unknown =
+                HashMap::from([("lava".to_string(), (DynamicImage::new_rgba8(1, 1), 1.0))]);
+            assert!(model.render_decorated(&unknown, 3).is_err());
+        }
+
+        #[test]
+        fn single_cell_patterns_fit_up_to_the_last_cell() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 3, 4);
+            assert!(model.pattern_fits(0, 0));
+            assert!(model.pattern_fits(2, 3));
+            assert!(!model.pattern_fits(3, 0));
+            assert!(!model.pattern_fits(0, 4));
+            assert!(!model.pattern_fits(-1, 0));
+            assert!(!model.pattern_fits(0, -1));
+
+            model.periodic = true;
+            assert!(model.pattern_fits(-1, 4));
+        }
+
+        #[test]
+        fn larger_patterns_stop_n_cells_before_the_edge() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 3, 4);
+            model.n = 2;
+            assert!(model.pattern_fits(1, 2));
+            assert!(!model.pattern_fits(2, 0));
+            assert!(!model.pattern_fits(0, 3));
+
+            let mut rng = ChaCha8Rng::seed_from_u64(0);
+            while let Some(node) = model.next_unobserved_node() {
+                assert!(node % 3 < 2 && node / 3 < 3);
+                model.observe(node, &mut rng);
+                if !model.propagate() {
+                    break;
+                }
+            }
+        }
+
+        #[test]
+        fn streaming_save_matches_render() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 5, 3);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let path = std::env::temp_dir().join(format!("streamed_{}.png", std::process::id()));
+            model.save_streaming(&path).unwrap();
+            let streamed = image::open(&path).unwrap().to_rgba8();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(streamed, model.render().unwrap());
+        }
+
+        #[test]
+        fn output_symmetry_mirrors_observations() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 6, 5);
+            model.set_output_symmetry(Some(OutputSymmetry::LeftRight));
+            assert!((0..1000).any(|seed| model.run(seed, usize::MAX)));
+            for y in 0..5 {
+                for x in 0..6 {
+                    let tile = model.observed[x + y * 6].unwrap();
+                    let mirrored = model.observed[5 - x + y * 6].unwrap();
+                    assert_eq!(mirrored, model.action[tile][4]);
+                }
+            }
+        }
+
+        #[test]
+        fn restore_returns_to_snapshot() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 4, 4);
+            let mut rng = ChaCha8Rng::seed_from_u64(5);
+            let node = model.next_unobserved_node().unwrap();
+            model.observe(node, &mut rng);
+            model.propagate();
+            let snapshot = model.snapshot();
+            let wave = model.wave.clone();
+            let entropies = model.entropies.clone();
+
+            while let Some(node) = model.next_unobserved_node() {
+                model.observe(node, &mut rng);
+                if !model.propagate() {
+                    break;
+                }
+            }
+            assert_ne!(model.wave, wave);
+
+            model.restore(&snapshot);
+            assert_eq!(model.wave, wave);
+            assert_eq!(model.entropies, entropies);
+        }
+
+        #[test]
+        fn required_tiles_always_appear() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 5, 5);
+            model.set_required_tiles(&["cross"]).unwrap();
+            let cross = model.base_tiles.iter().find(|b| b.name == "cross").unwrap();
+            let crosses = cross.first..cross.first + cross.cardinality;
+            for seed in 0..10 {
+                if model.run(seed, usize::MAX) {
+                    assert!(model.observed.iter().any(|t| crosses.contains(&t.unwrap())));
+                }
+            }
+
+            assert!(model.set_required_tiles(&["lava"]).is_err());
+            assert!(model.set_required_tiles(&["cross 2"]).is_err());
+        }
+
+        #[test]
+        fn banning_every_tile_keeps_entropy_finite() {
+            let mut model = knot_model(
+                r#"
+                tiles = [
+                    { name="corner.png", symmetry="L", weight=1e-300 },
+                    { name="empty.png", symmetry="X", weight=1e-300 },
+                ]
+                neighbors = [ { left="corner *", right="empty" } ]
+                "#,
+                2,
+                1,
+            );
+            for t in 0..model.num_tiles {
+                model.ban(0, t);
+                assert!(model.entropies[0].is_finite());
+            }
+            assert_eq!(model.sums_of_ones[0], 0);
+
+            assert_eq!(model.next_unobserved_node(), Some(1));
+        }
+
+        #[test]
+        fn compiled_tileset_round_trips() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 4, 4);
+            let path = std::env::temp_dir().join(format!("knot_{}.bin", std::process::id()));
+            model.compile(&path).unwrap();
+            let mut loaded =
+                SimpleTiled::from_compiled(&path, 4, 4, false, Heuristic::Entropy).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(loaded.tile_names, model.tile_names);
+            assert_eq!(loaded.propagator, model.propagator);
+            assert_eq!(loaded.tiles.len(), model.tiles.len());
+            for (a, b) in loaded.tiles.iter().zip(&model.tiles) {
+                assert_eq!(a.image.to_rgba8(), b.image.to_rgba8());
+                assert_eq!(a.weight, b.weight);
+            }
+
+            let seed = (0..100).find(|&seed| model.run(seed, usize::MAX)).unwrap();
+            assert!(loaded.run(seed, usize::MAX));
+            assert_eq!(loaded.observed, model.observed);
+        }
+
+        #[test]
+        fn verify_accepts_output_and_flags_edits() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 5, 4);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let mut image = model.render().unwrap();
+            assert_eq!(
+                model
+                    .verify_image(&DynamicImage::ImageRgba8(image.clone()))
+                    .unwrap(),
+                vec![]
+            );
+
+            image.put_pixel(12, 3, Rgba([1, 2, 3, 4]));
+            let violations = model
+                .verify_image(&DynamicImage::ImageRgba8(image))
+                .unwrap();
+            assert_eq!(violations, vec![Violation::UnknownTile { x: 1, y: 0 }]);
+
+            assert!(model
+                .verify_image(&DynamicImage::new_rgba8(15, 10))
+                .is_err());
+        }
+
+        #[test]
+        fn tiles_of_another_size_are_rejected() {
+            let folder = std::env::temp_dir().join(format!("sizes_{}", std::process::id()));
+            std::fs::create_dir_all(&folder).unwrap();
+            RgbaImage::new(4, 4).save(folder.join("small.png")).unwrap();
+            RgbaImage::new(6, 4).save(folder.join("wide.png")).unwrap();
+            let mut config = Config::new();
+            config
+                .add_tile(Tile::new("small.png", Some("X"), None))
+                .add_tile(Tile::new("wide.png", Some("X"), None))
+                .add_neighbor("small", "wide");
+            let err = SimpleTiled::new(
+                config,
+                folder.to_str().unwrap(),
+                2,
+                2,
+                false,
+                Heuristic::Entropy,
+            )
+            .unwrap_err();
+            std::fs::remove_dir_all(&folder).unwrap();
+            assert_eq!(
+                err.to_string(),
+                "Tile 'wide.png' has a 6x4 pixel variant but the tileset uses 4x4 pixels"
+            );
+        }
+
+        #[test]
+        fn duplicate_tile_names_are_rejected() {
+            let mut config: Config = toml::from_str(include_str!("../knot/config.toml")).unwrap();
+            config.add_tile(Tile::new("corner.png", Some("X"), None));
+            let err = SimpleTiled::new(config, KNOT, 2, 2, false, Heuristic::Entropy).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Tile 'corner.png' is named 'corner' like an earlier tile, rename one of them"
+            );
+        }
+
+        #[test]
+        fn rectangular_tiles_render_without_gaps() {
+            let folder = std::env::temp_dir().join(format!("rect_{}", std::process::id()));
+            std::fs::create_dir_all(&folder).unwrap();
+            let colors = [("red", [255, 0, 0, 255]), ("blue", [0, 0, 255, 255])];
+            for (name, color) in colors {
+                RgbaImage::from_pixel(3, 2, Rgba(color))
+                    .save(folder.join(format!("{name}.png")))
+                    .unwrap();
+            }
+            let config: Config = toml::from_str(
+                r#"
+                tiles = [
+                    { name="red.png", symmetry="X" },
+                    { name="blue.png", symmetry="X" },
+                ]
+                neighbors = [
+                    { left="red", right="blue" },
+                    { left="blue", right="red" },
+                ]
+                "#,
+            )
+            .unwrap();
+            let mut model = SimpleTiled::new(
+                config,
+                folder.to_str().unwrap(),
+                4,
+                3,
+                false,
+                Heuristic::Entropy,
+            )
+            .unwrap();
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+
+            let image = model.render().unwrap();
+            assert_eq!(image.dimensions(), (12, 6));
+            for y in 0..3 {
+                for x in 0..4 {
+                    let color = colors[model.observed[x + y * 4].unwrap()].1;
+                    for py in 0..2 {
+                        for px in 0..3 {
+                            assert_eq!(
+                                image.get_pixel(x as u32 * 3 + px, y as u32 * 2 + py).0,
+                                color
+                            );
+                        }
+                    }
+                }
+            }
+
+            let path = folder.join("streamed.png");
+            model.save_streaming(&path).unwrap();
+            let streamed = image::open(&path).unwrap().to_rgba8();
+            std::fs::remove_dir_all(&folder).unwrap();
+            assert_eq!(streamed, image);
+        }
+
+        /// A three coloring of the grid, where neighbors must differ. It often
+        /// contradicts once a cell is surrounded by all three colors.
+        fn coloring_model(name: &str, width: usize, height: usize) -> SimpleTiled {
+            let folder = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+            std::fs::create_dir_all(&folder).unwrap();
+            for (name, color) in [
+                ("r", [255, 0, 0, 255]),
+                ("g", [0, 255, 0, 255]),
+                ("b", [0, 0, 255, 255]),
+            ] {
+                RgbaImage::from_pixel(2, 2, Rgba(color))
+                    .save(folder.join(format!("{name}.png")))
+                    .unwrap();
+            }
+            let mut config = Config::new();
+            let colors = ["r", "g", "b"];
+            for (i, tile) in colors.iter().enumerate() {
+                config.add_tile(Tile::new(&format!("{tile}.png"), Some("X"), None));
+                // Mirroring adds the other order
+                for other in &colors[i + 1..] {
+                    config.add_neighbor(tile, other);
+                }
+            }
+            let model = SimpleTiled::new(
+                config,
+                folder.to_str().unwrap(),
+                width,
+                height,
+                false,
+                Heuristic::Entropy,
+            )
+            .unwrap();
+            std::fs::remove_dir_all(&folder).unwrap();
+            model
+        }
+
+        /// A model of `config` built from in-memory tile images, each named like
+        /// its tile in the config.
+        fn image_model(
+            config: Config,
+            tiles: &[(&str, RgbaImage)],
+            width: usize,
+            height: usize,
+            periodic: bool,
+        ) -> Result<SimpleTiled, Box<dyn Error>> {
+            let images = tiles
+                .iter()
+                .map(|(name, image)| {
+                    let image = DynamicImage::ImageRgba8(image.clone());
+                    (name.to_string(), image, 1.)
+                })
+                .collect();
+            SimpleTiled::from_tiles(config, images, width, height, periodic, Heuristic::Entropy)
+        }
+
+        /// A `size` pixel square tile image of a single `color`.
+        fn solid(size: u32, color: [u8; 4]) -> RgbaImage {
+            RgbaImage::from_pixel(size, size, Rgba(color))
+        }
+
+        #[test]
+        fn example_weights_follow_tile_frequency() {
+            let model = coloring_model("example_weights", 1, 1);
+            // Off by a little from the tiles, still closest to red and green
+            let mut example = RgbaImage::from_pixel(6, 4, Rgba([250, 10, 0, 255]));
+            for (x, y, pixel) in example.enumerate_pixels_mut() {
+                if x >= 4 {
+                    *pixel = Rgba([0, 240, 20, 255]);
+                }
+                if (x + y) % 2 == 0 {
+                    pixel.0[3] = 250;
+                }
+            }
+            let weights = model
+                .example_weights(&DynamicImage::ImageRgba8(example))
+                .unwrap();
+            assert_eq!(
+                weights,
+                vec![
+                    ("r".to_string(), 4.),
+                    ("g".to_string(), 2.),
+                    ("b".to_string(), 0.02)
+                ]
+            );
+
+            let config: Config = toml::from_str(
+                r#"
+                tiles = [
+                    { name="r.png", symmetry="X", weight=7.5 },
+                    { name="g.png" },
+                    { name="b.png" },
+                ]
+                neighbors = [{ left="r", right="g" }]
+                "#,
+            )
+            .unwrap();
+            let block = config.weight_block(&weights[..2]).unwrap();
+            let tiles: Config = toml::from_str(&format!("{block}neighbors = []")).unwrap();
+            let weights: Vec<_> = tiles.tiles.iter().map(|tile| tile.weight).collect();
+            assert_eq!(weights, [Some(4.), Some(2.), None]);
+            assert_eq!(tiles.tiles[0].symmetry.as_deref(), Some("X"));
+            assert_eq!(tiles.tiles[1].symmetry, None);
+        }
+
+        #[test]
+        fn normalized_example_weights_cover_every_variant() {
+            let mut config: Config = toml::from_str(
+                r#"
+                tiles = [{ name="r.png", symmetry="L" }, { name="g.png" }]
+                neighbors = [{ left="r", right="g" }]
+                "#,
+            )
+            .unwrap();
+            config.set_normalize_weights(true);
+            let weights = [("r".to_string(), 1.5), ("g".to_string(), 2.)];
+            let block = config.weight_block(&weights).unwrap();
+            let neighbors = r#"neighbors = [{ left="r", right="g" }]"#;
+            let mut tiles: Config = toml::from_str(&format!("{block}{neighbors}")).unwrap();
+            let written: Vec<_> = tiles.tiles.iter().map(|tile| tile.weight).collect();
+            assert_eq!(written, [Some(6.), Some(2.)]);
+
+            // Loaded back with `normalize_weights`, each variant gets its weight
+            tiles.set_normalize_weights(true);
+            let model = SimpleTiled::from_tiles(
+                tiles,
+                ["r", "g"]
+                    .into_iter()
+                    .zip(written)
+                    .map(|(name, weight)| {
+                        let image = DynamicImage::ImageRgba8(RgbaImage::new(2, 2));
+                        (name.to_string(), image, weight.unwrap())
+                    })
+                    .collect(),
+                1,
+                1,
+                false,
+                Heuristic::Entropy,
+            )
+            .unwrap();
+            let variant_weights: Vec<f64> = model.tiles.iter().map(|tile| tile.weight).collect();
+            assert_eq!(variant_weights, [1.5, 1.5, 1.5, 1.5, 2.]);
+        }
+
+        #[test]
+        fn reset_cluster_recovers_valid_tilings() {
+            let mut model = coloring_model("coloring", 12, 12);
+
+            let restart_failures = (0..20)
+                .filter(|&seed| !model.try_seed(seed, usize::MAX))
+                .count();
+            model.set_recovery(Recovery::ResetCluster);
+            let mut recovered = 0;
+            let mut failures = 0;
+            for seed in 0..20 {
+                if model.run(seed, usize::MAX) {
+                    recovered += (model.stats().local_resets > 0) as usize;
+                    let image = DynamicImage::ImageRgba8(model.render().unwrap());
+                    assert_eq!(model.verify_image(&image).unwrap(), vec![]);
+                } else {
+                    failures += 1;
+                }
+            }
+            assert!(recovered > 0);
+            assert!(failures < restart_failures);
+        }
+
+        #[test]
+        fn adaptive_retries_avoid_failed_openings() {
+            let mut model = coloring_model("adaptive", 12, 12);
+            model.set_adaptive_retries(true);
+            let seed = (0..100).find(|&seed| !model.run(seed, usize::MAX)).unwrap();
+            assert!(!model.failure_memory.is_empty());
+            assert!(model.failure_memory.keys().count() <= OPENING_OBSERVATIONS);
+
+            // The same seed observes the same cell first but avoids the tile
+            // that is remembered to fail there
+            let opening = |model: &SimpleTiled| {
+                let cell = model
+                    .sums_of_ones
+                    .iter()
+                    .position(|&remaining| remaining == 1)
+                    .unwrap();
+                (
+                    cell,
+                    model.wave[cell]
+                        .iter()
+                        .position(|&possible| possible)
+                        .unwrap(),
+                )
+            };
+            model.run(seed, 1);
+            let (cell, tile) = opening(&model);
+            model.failure_memory.insert((cell, tile), 1_000_000);
+            model.run(seed, 1);
+            assert_eq!(opening(&model).0, cell);
+            assert_ne!(opening(&model).1, tile);
+
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            assert!(model.failure_memory.is_empty());
+            model.failure_memory.insert((0, 0), 1);
+            model.set_adaptive_retries(false);
+            assert!(model.failure_memory.is_empty());
+        }
+
+        #[test]
+        fn observed_tiles_follow_grid_order() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 3, 2);
+            assert_eq!(model.observed_tiles(GridOrder::RowMajor), vec![None; 6]);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+
+            let rows = model.observed_tiles(GridOrder::RowMajor);
+            assert_eq!(rows, model.observed);
+            let at = |x: usize, y: usize| rows[x + y * 3];
+            assert_eq!(
+                model.observed_tiles(GridOrder::ColumnMajor),
+                vec![at(0, 0), at(0, 1), at(1, 0), at(1, 1), at(2, 0), at(2, 1)]
+            );
+            assert_eq!(
+                model.observed_tiles(GridOrder::YUp),
+                vec![at(0, 1), at(1, 1), at(2, 1), at(0, 0), at(1, 0), at(2, 0)]
+            );
+
+            assert_eq!(model.to_string(), model.format_grid(GridOrder::RowMajor));
+            assert_eq!(model.format_grid(GridOrder::ColumnMajor).lines().count(), 3);
+            let name = |t: Option<usize>| model.tile_names()[t.unwrap()].clone();
+            assert!(model
+                .format_grid(GridOrder::YUp)
+                .starts_with(&format!("{},\t", name(at(0, 1)))));
+        }
+
+        #[test]
+        fn symmetry_from_file_name() {
+            let tile = |name: &str, symmetry: Option<&str>| Tile::new(name, symmetry, None);
+            assert_eq!(
+                tile("corner.L.png", None).name_and_symmetry(),
+                Ok(("corner", Symmetry::L))
+            );
+            assert_eq!(
+                tile("dskew.\\.png", None).name_and_symmetry(),
+                Ok(("dskew", Symmetry::Diagonal))
+            );
+            assert_eq!(
+                tile("empty.png", None).name_and_symmetry(),
+                Ok(("empty", Symmetry::X))
+            );
+            assert_eq!(
+                tile("corner.L.png", Some("T")).name_and_symmetry(),
+                Ok(("corner.L", Symmetry::T))
+            );
+            assert!(tile("corner.Q.png", None).name_and_symmetry().is_err());
+            for code in ["Labc", "", "l"] {
+                let tile = tile("corner.png", Some(code));
+                assert_eq!(
+                    tile.name_and_symmetry().unwrap_err(),
+                    format!("Tile 'corner.png': Unrecognized symmetry '{code}', expected one of X L T I \\ F Z")
+                );
+            }
+            for symmetry in Symmetry::ALL {
+                assert_eq!(symmetry.code().parse(), Ok(symmetry));
+            }
+        }
+
+        #[test]
+        fn frontier_only_observes_next_to_collapsed_cells() {
+            // Nothing constrains anything, so only the frontier keeps
+            // observations together
+            let mut model = knot_model(
+                r#"
+                tiles = [
+                    { name="empty.png", symmetry="X" },
+                    { name="cross.png", symmetry="X" },
+                ]
+                neighbors = [
+                    { left="empty", right="empty" }, { left="empty", right="cross" },
+                    { left="cross", right="cross" }, { left="cross", right="empty" },
+                ]
+                "#,
+                16,
+                16,
+            );
+            model.set_frontier_only(true);
+            for seed in 0..10 {
+                for limit in [1, 5, 20] {
+                    model.run(seed, limit);
+                    let Some(node) = model.next_unobserved_node() else {
+                        continue;
+                    };
+                    assert!(Direction::ALL.into_iter().any(|direction| {
+                        model
+                            .neighbor(node, direction)
+                            .is_some_and(|neighbor| model.sums_of_ones[neighbor] == 1)
+                    }));
+                }
+            }
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+        }
+
+        #[test]
+        fn pinned_seed_gives_the_same_first_observation() {
+            // Golden values, a change here means old seeds no longer reproduce
+            let mut rng = ChaCha8Rng::seed_from_u64(7);
+            assert_eq!(unit_f64(&mut rng), 0.15779609702061936);
+            let mut model = knot_model(include_str!("../knot/config.toml"), 8, 8);
+            model.set_record_events(true);
+            model.run(7, usize::MAX);
+            let first = model
+                .events()
+                .iter()
+                .find(|event| matches!(event, SolveEvent::Observe { .. }));
+            assert_eq!(first, Some(&SolveEvent::Observe { cell: 22, tile: 4 }));
+        }
+
+        #[test]
+        fn random_from_distr_only_picks_weighted_tiles() {
+            let weights = [0., 0., 0., 1e-300];
+            assert_eq!(random_from_distr(&weights, 0.), 3);
+            assert_eq!(random_from_distr(&weights, 0.5), 3);
+            // As if rounding left the running sum short of the threshold
+            assert_eq!(random_from_distr(&weights, 1. + f64::EPSILON), 3);
+            assert_eq!(random_from_distr(&[0., 2., 0.], 1.5), 1);
+            assert_eq!(random_from_distr(&[0., 0.], 0.5), 0);
+        }
+
+        #[test]
+        fn matching_edges_hold_equal_tiles() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 6, 5);
+            model.set_edge_matching(Some(EdgeMatching::Both));
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let at = |x: usize, y: usize| model.observed[x + y * 6];
+            for y in 0..5 {
+                assert_eq!(at(0, y), at(5, y));
+            }
+            for x in 0..6 {
+                assert_eq!(at(x, 0), at(x, 4));
+            }
+
+            model.set_edge_matching(Some(EdgeMatching::Vertical));
+            assert_eq!(model.edge_partners(0), [None, Some(24)]);
+            assert_eq!(model.edge_partners(7), [None, None]);
+        }
+
+        #[test]
+        fn atlas_shows_every_labeled_tile() {
+            let model = knot_model(include_str!("../knot/config.toml"), 1, 1);
+            let atlas = model.tile_atlas();
+            // 13 variants fill 4 columns of cells sized for the widest label
+            let cell_width = text_width("3:corner 3") + 4;
+            let cell_height = 10 + GLYPH_HEIGHT + 6;
+            assert_eq!(atlas.dimensions(), (4 * cell_width, 4 * cell_height));
+
+            // Tile 5 sits in the second column of the second row
+            let x = cell_width + 2 + (text_width("3:corner 3") - 10) / 2;
+            let y = cell_height + 2;
+            let tile = imageops::crop_imm(&atlas, x, y, 10, 10).to_image();
+            assert_eq!(tile, model.tiles[5].image.to_rgba8());
+            let label = imageops::crop_imm(&atlas, x, y + 12, 10, GLYPH_HEIGHT).to_image();
+            assert!(label.pixels().any(|pixel| pixel.0 == [0, 0, 0, 255]));
+        }
+
+        #[test]
+        fn comparison_sheet_centers_each_output() {
+            let mut knot = knot_model(include_str!("../knot/config.toml"), 2, 2);
+            let mut colors = coloring_model("comparison_sheet", 3, 3);
+            assert!((0..100).any(|seed| knot.run(seed, usize::MAX)));
+            assert!((0..100).any(|seed| colors.run(seed, usize::MAX)));
+            let sheet =
+                SimpleTiled::comparison_sheet(&[("knot", &knot), ("colors", &colors)]).unwrap();
+            // Slots are sized for the 20px knot output and the wider "colors" label
+            let inner_width = text_width("colors");
+            let cell_width = inner_width + 4;
+            assert_eq!(sheet.dimensions(), (2 * cell_width, 20 + GLYPH_HEIGHT + 6));
+
+            let x = cell_width + 2 + (inner_width - 6) / 2;
+            let output = imageops::crop_imm(&sheet, x, 2 + 7, 6, 6).to_image();
+            assert_eq!(output, colors.render().unwrap());
+        }
+
+        #[test]
+        fn tie_break_steers_mrv_ties() {
+            let config = include_str!("../knot/config.toml");
+            let mut model = knot_model(config, 5, 5);
+            model.set_heuristic(Heuristic::MRV);
+            model.set_tie_break(TieBreak::Center);
+            model.run(3, 0);
+            assert_eq!(model.next_unobserved_node(), Some(12));
+
+            model.set_tie_break(TieBreak::Observed);
+            for seed in 0..10 {
+                model.run(seed, 1);
+                let node = model.next_unobserved_node().unwrap();
+                assert!(model.collapsed_neighbors(node) > 0);
+            }
+        }
+
+        #[test]
+        fn retries_derive_from_the_image_seed() {
+            let base = derive_seed(42, 3);
+            assert_eq!(crate::attempt_seed(42, 3, 0), base);
+            assert_eq!(crate::attempt_seed(42, 3, 2), derive_seed(base, 2));
+            assert_ne!(crate::attempt_seed(42, 0, 0), 42);
+        }
+
+        #[test]
+        fn deterministic_runs_ignore_the_seed() {
+            let config = include_str!("../knot/config.toml").replace(
+                r#"{ name="cross.png", symmetry="I" }"#,
+                r#"{ name="cross.png", symmetry="I", weight=2.0 }"#,
+            );
+            let mut model = knot_model(&config, 6, 6);
+            model.set_heuristic(Heuristic::ScanLine);
+            model.set_deterministic(true);
+            assert!(model.run(1, usize::MAX));
+            let first = model.observed.clone();
+            assert!(model.run(2, usize::MAX));
+            assert_eq!(model.observed, first);
+            assert!(model.tile_names[first[0].unwrap()].starts_with("cross"));
+        }
+
+        #[test]
+        fn batch_observe_picks_separate_cells() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 20, 20);
+            let first = model.next_unobserved_node().unwrap();
+            let batch: Vec<usize> = std::iter::once(first)
+                .chain(model.batch_companions(first, 5))
+                .collect();
+            assert_eq!(batch.len(), 6);
+            for &i in &batch {
+                for direction in Direction::ALL {
+                    assert!(!model
+                        .neighbor(i, direction)
+                        .is_some_and(|n| batch.contains(&n)));
+                }
+            }
+
+            model.set_batch_observe(8);
+            model.run(0, 12);
+            assert_eq!(model.stats().observations, 12);
+            let mut solved = 0;
+            for seed in 0..10 {
+                if model.run(seed, usize::MAX) {
+                    solved += 1;
+                    let image = DynamicImage::ImageRgba8(model.render().unwrap());
+                    assert_eq!(model.verify_image(&image).unwrap(), vec![]);
+                }
+            }
+            assert!(solved > 0);
+        }
+
+        #[test]
+        fn neighbor_orientation_must_exist() {
+            let config = |neighbors: &str| {
+                let config: Config = toml::from_str(&format!(
+                    r#"
+                    tiles = [
+                        {{ name="corner.png", symmetry="L" }},
+                        {{ name="line.png", symmetry="I" }},
+                    ]
+                    neighbors = [ {neighbors} ]
+                    "#
+                ))
+                .unwrap();
+                SimpleTiled::new(config, KNOT, 2, 2, false, Heuristic::Entropy)
+            };
+            assert!(config(r#"{ left="corner 3", right="line 1" }"#).is_ok());
+            let err = config(r#"{ left="corner", right="line 2" }"#).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "neighbor references orientation 2 of 'line' which only has 2 variants"
+            );
+            assert!(config(r#"{ left="corner 4", right="line" }"#).is_err());
+            assert!(config(r#"{ left="knot", right="line" }"#).is_err());
+        }
+
+        #[test]
+        fn repeated_neighbor_rules_are_redundant() {
+            let model = knot_model(include_str!("../knot/config.toml"), 1, 1);
+            let action: Vec<[i32; 8]> = model.action.iter().map(|a| a.map(|t| t as i32)).collect();
+            let first_occurence = model
+                .base_tiles
+                .iter()
+                .map(|base| (base.name.clone(), base.first))
+                .collect();
+            let cardinalities = model
+                .base_tiles
+                .iter()
+                .map(|base| (base.name.clone(), base.cardinality))
+                .collect();
+
+            let mut config = Config::new();
+            config
+                .add_neighbor("corner", "line")
+                .add_neighbor("line", "line")
+                .add_neighbor("corner", "line")
+                // The mirror image of the first rule
+                .add_neighbor("line", "corner 1")
+                .add_neighbor("line 1", "line 1")
+                .add_neighbor("corner 1", "corner");
+            let rules = neighbor_rules(
+                config.neighbors(),
+                &HashMap::new(),
+                &action,
+                &first_occurence,
+                &cardinalities,
+                true,
+            )
+            .unwrap();
+            assert_eq!(
+                redundant_neighbors(&rules),
+                vec![(2, vec![0]), (3, vec![0])]
+            );
+        }
+
+        #[test]
+        fn warnings_are_handed_to_the_caller() {
+            let config = include_str!("../knot/config.toml").replacen(
+                "neighbors = [",
+                r#"neighbors = [{ left="cross", right="cross" },"#,
+                1,
+            );
+            let mut model = knot_model(&config, 4, 4);
+            let warnings = model.take_warnings();
+            assert_eq!(warnings.len(), 1);
+            assert_eq!(
+                warnings[0],
+                r#"neighbors[14] { left="cross", right="cross" } is already implied by neighbors [0]"#
+            );
+            assert!(model.take_warnings().is_empty());
+
+            model.set_stack_warning(Some(0));
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let warnings = model.take_warnings();
+            assert!(!warnings.is_empty());
+            assert!(warnings
+                .iter()
+                .all(|warning| warning.starts_with("propagation stack peaked")));
+        }
+
+        #[test]
+        fn stop_entropy_ends_observation_early() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 10, 10);
+            model.set_stop_entropy(Some(f64::INFINITY));
+            assert!(model.run(0, usize::MAX));
+            assert_eq!(model.stats().observations, 0);
+            assert!(model.observed.iter().all(Option::is_some));
+
+            let mut full = knot_model(include_str!("../knot/config.toml"), 10, 10);
+            let seed = (0..100).find(|&seed| full.run(seed, usize::MAX)).unwrap();
+            model.set_stop_entropy(Some(model.starting_entropy / 2.));
+            assert!(model.run(seed, usize::MAX));
+            assert!(model.stats().observations > 0);
+            assert!(model.stats().observations < full.stats().observations);
+        }
+
+        #[test]
+        fn flag_grid_follows_observed_tiles() {
+            let config = format!(
+                "{}\n{}",
+                include_str!("../knot/config.toml"),
+                r#"
+                [flags]
+                empty = { walkable = true }
+                line = { walkable = true, wall = true }
+                "line 1" = { walkable = false }
+                "#
+            );
+            let mut model = knot_model(&config, 4, 4);
+            assert_eq!(model.flag_grid("walkable"), None);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            assert_eq!(model.flag_grid("spawn"), None);
+            let walkable = model.flag_grid("walkable").unwrap();
+            for (y, row) in walkable.iter().enumerate() {
+                for (x, &set) in row.iter().enumerate() {
+                    let name = &model.tile_names[model.observed[x + y * 4].unwrap()];
+                    assert_eq!(set, name == "empty 0" || name == "line 0");
+                }
+            }
+
+            let path = std::env::temp_dir().join(format!("flags_{}", std::process::id()));
+            model.save_flag_csv(&path, "walkable").unwrap();
+            let csv = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(csv.lines().count(), 4);
+            assert!(csv.starts_with(if walkable[0][0] { "1," } else { "0," }));
+
+            let unknown = format!("{config}\nwall = {{ walkable = false }}");
+            let config: Config = toml::from_str(&unknown).unwrap();
+            assert!(SimpleTiled::new(config, KNOT, 4, 4, false, Heuristic::Entropy).is_err());
+        }
+
+        #[test]
+        fn placed_tiles_are_reported_by_name() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 6, 6);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let mut spawns = vec![];
+            model
+                .for_each_placed(&["cross", "line 1"], |x, y, t| spawns.push((x, y, t)))
+                .unwrap();
+            let expected: Vec<(usize, usize, usize)> = (0..36)
+                .filter_map(|i| {
+                    let t = model.observed[i]?;
+                    let name = &model.tile_names[t];
+                    (name.starts_with("cross") || name == "line 1").then_some((i % 6, i / 6, t))
+                })
+                .collect();
+            assert_eq!(spawns, expected);
+
+            let mut count = 0;
+            model.for_each_placed(&[], |_, _, _| count += 1).unwrap();
+            assert_eq!(count, 36);
+            assert!(model.for_each_placed(&["chest"], |_, _, _| ()).is_err());
+        }
+
+        #[test]
+        fn tiles_can_come_from_memory() {
+            let config_str = include_str!("../knot/config.toml");
+            let config: Config = toml::from_str(config_str).unwrap();
+            let mut images: Vec<TileImage> = config
+                .tiles
+                .iter()
+                .map(|tile| {
+                    let (name, _) = tile.name_and_symmetry().unwrap();
+                    let image = image::open(format!("{KNOT}/{}", tile.name)).unwrap();
+                    (name.to_string(), image, tile.weight.unwrap_or(1.))
+                })
+                .collect();
+            let mut model = SimpleTiled::from_tiles(
+                config.clone(),
+                images.clone(),
+                8,
+                8,
+                false,
+                Heuristic::Entropy,
+            )
+            .unwrap();
+            let mut from_folder = knot_model(config_str, 8, 8);
+            assert_eq!(model.tile_names, from_folder.tile_names);
+            assert_eq!(model.propagator, from_folder.propagator);
+            assert_eq!(model.run(3, usize::MAX), from_folder.run(3, usize::MAX));
+            assert_eq!(model.observed, from_folder.observed);
+
+            let extra = images[0].1.clone();
+            images.push(("chest".to_string(), extra, 1.));
+            let err = SimpleTiled::from_tiles(
+                config.clone(),
+                images[1..].to_vec(),
+                8,
+                8,
+                false,
+                Heuristic::Entropy,
+            );
+            assert!(err.is_err());
+            assert!(
+                SimpleTiled::from_tiles(config, images, 8, 8, false, Heuristic::Entropy).is_err()
+            );
+        }
+
+        #[test]
+        fn mapped_ids_replace_tile_names() {
+            let config = format!(
+                "{}\n{}",
+                include_str!("../knot/config.toml"),
+                r#"
+                [mapping]
+                corner = 1
+                cross = 2
+                empty = "air"
+                line = 4
+                "line 1" = "vertical, line"
+                t = 5
+                "#
+            );
+            let mut model = knot_model(&config, 3, 3);
+            assert_eq!(model.observed_mapped(), None);
+            let seed = (0..100).find(|&seed| model.run(seed, usize::MAX)).unwrap();
+            let mapped = model.observed_mapped().unwrap();
+            assert_eq!(mapped.len(), 3);
+            for (y, row) in mapped.iter().enumerate() {
+                for (x, id) in row.iter().enumerate() {
+                    let name = &model.tile_names[model.observed[x + y * 3].unwrap()];
+                    let expected = match name.as_str() {
+                        "empty 0" => MappedId::Name("air".to_string()),
+                        "line 1" => MappedId::Name("vertical, line".to_string()),
+                        name if name.starts_with("corner") => MappedId::Number(1),
+                        name if name.starts_with("cross") => MappedId::Number(2),
+                        name if name.starts_with("line") => MappedId::Number(4),
+                        _ => MappedId::Number(5),
+                    };
+                    assert_eq!(id, &expected);
+                }
+            }
+
+            let path = std::env::temp_dir().join(format!("mapped_{}", std::process::id()));
+            model.compile(&path).unwrap();
+            let mut loaded =
+                SimpleTiled::from_compiled(&path, 3, 3, false, Heuristic::Entropy).unwrap();
+            assert!(loaded.run(seed, usize::MAX));
+            assert_eq!(loaded.observed_mapped(), Some(mapped.clone()));
+            model.save_mapped_csv(&path).unwrap();
+            let csv = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(csv.lines().count(), 3);
+            let first = mapped[0][0]
+                .to_string()
+                .replace("vertical, line", "\"vertical, line\"");
+            assert!(csv.starts_with(&format!("{first},")));
+
+            let missing = format!("{config}\nwall = 7");
+            let config: Config = toml::from_str(&missing).unwrap();
+            assert!(SimpleTiled::new(config, KNOT, 3, 3, false, Heuristic::Entropy).is_err());
+        }
+
+        #[test]
+        fn render_over_blends_with_base() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 3, 2);
+            let base = RgbaImage::from_pixel(30, 20, Rgba([0, 0, 255, 255]));
+            assert!(model.render_over(&base, 0.5).is_err());
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            assert!(model.render_over(&RgbaImage::new(30, 30), 0.5).is_err());
+
+            let tiles = model.render().unwrap();
+            assert_eq!(model.render_over(&base, 0.).unwrap(), base);
+            assert_eq!(model.render_over(&base, 2.).unwrap(), tiles);
+            let half = model.render_over(&base, 0.5).unwrap();
+            for (blended, tile) in half.pixels().zip(tiles.pixels()) {
+                assert_eq!(blended.0[3], 255);
+                let expected = [tile.0[0] / 2, tile.0[1] / 2, tile.0[2] / 2 + 127];
+                for (channel, expected) in blended.0.iter().zip(expected) {
+                    assert!(channel.abs_diff(expected) <= 2);
+                }
+            }
+        }
+
+        #[test]
+        fn normalized_weights_split_among_variants() {
+            let weights = |normalize: bool| {
+                let mut config: Config =
+                    toml::from_str(include_str!("../knot/config.toml")).unwrap();
+                config.set_normalize_weights(normalize);
+                let model =
+                    SimpleTiled::new(config, KNOT, 1, 1, false, Heuristic::Entropy).unwrap();
+                let weight = |name: &str| {
+                    let t = model.tile_names.iter().position(|n| n == name).unwrap();
+                    model.tiles[t].weight
+                };
+                [weight("corner 3"), weight("line 1"), weight("empty 0")]
+            };
+            assert_eq!(weights(false), [1., 1., 1.]);
+            assert_eq!(weights(true), [0.25, 0.5, 1.]);
+        }
+
+        #[test]
+        fn entropy_follows_the_remaining_weights() {
+            let folder = std::env::temp_dir().join(format!("entropy_{}", std::process::id()));
+            std::fs::create_dir_all(&folder).unwrap();
+            let mut config = Config::new();
+            for (name, weight) in [("a", 1.), ("b", 2.), ("c", 3.)] {
+                RgbaImage::new(1, 1)
+                    .save(folder.join(format!("{name}.png")))
+                    .unwrap();
+                config.add_tile(Tile::new(&format!("{name}.png"), Some("X"), Some(weight)));
+                for other in ["a", "b", "c"] {
+                    config.add_neighbor(name, other);
+                }
+            }
+            let mut model = SimpleTiled::new(
+                config,
+                folder.to_str().unwrap(),
+                1,
+                1,
+                false,
+                Heuristic::Entropy,
+            )
+            .unwrap();
+            std::fs::remove_dir_all(&folder).unwrap();
+            let entropy = |weights: &[f64]| {
+                let sum: f64 = weights.iter().sum();
+                -weights
+                    .iter()
+                    .map(|w| w / sum * (w / sum).ln())
+                    .sum::<f64>()
+            };
+            assert!((model.starting_entropy - entropy(&[1., 2., 3.])).abs() < 1e-12);
+            model.clear();
+            model.ban(0, 0);
+            assert!((model.entropies[0] - entropy(&[2., 3.])).abs() < 1e-12);
+        }
+
+        #[test]
+        fn log_weights_are_exponentiated() {
+            let mut config = Config::new();
+            config
+                .add_tile(Tile::new("corner.png", Some("L"), Some(1.)))
+                .add_tile(Tile::new("empty.png", Some("X"), None))
+                .add_neighbor("corner", "empty")
+                .set_weights_are_log(true)
+                .set_normalize_weights(true);
+            let model = SimpleTiled::new(config, KNOT, 1, 1, false, Heuristic::Entropy).unwrap();
+            let weights: Vec<f64> = model.tiles.iter().map(|tile| tile.weight).collect();
+            assert_eq!(
+                weights,
+                [1f64.exp() / 4.; 4]
+                    .into_iter()
+                    .chain([1.])
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        #[test]
+        fn recorded_events_replay_to_the_same_grid() {
+            let mut model = coloring_model("events", 6, 6);
+            model.set_record_events(true);
+            model.set_recovery(Recovery::ResetCluster);
+            assert!(model.run(0, usize::MAX) || (1..100).any(|seed| model.run(seed, usize::MAX)));
+            let events = model.events().to_vec();
+            let observations = events
+                .iter()
+                .filter(|event| matches!(event, SolveEvent::Observe { .. }))
+                .count();
+            assert_eq!(observations, model.stats().observations);
+
+            let mut replayed = coloring_model("events_replay", 6, 6);
+            assert!(replayed.replay(&events));
+            assert_eq!(replayed.observed, model.observed);
+            assert!(replayed.events().is_empty());
+        }
+
+        #[test]
+        fn tiles_below_minimum_size_are_rejected() {
+            let folder = std::env::temp_dir().join(format!("tiny_tiles_{}", std::process::id()));
+            std::fs::create_dir_all(&folder).unwrap();
+            RgbaImage::from_pixel(2, 3, Rgba([255, 0, 0, 255]))
+                .save(folder.join("dot.png"))
+                .unwrap();
+            let mut config = Config::new();
+            config
+                .add_tile(Tile::new("dot.png", None, None))
+                .add_neighbor("dot", "dot");
+            let model = |config: &Config| {
+                SimpleTiled::new(
+                    config.clone(),
+                    folder.to_str().unwrap(),
+                    2,
+                    2,
+                    false,
+                    Heuristic::Entropy,
+                )
+            };
+            assert!(model(&config).is_ok());
+            assert!(model(config.clone().set_min_tile_size(2)).is_ok());
+            let err = model(config.clone().set_min_tile_size(3)).unwrap_err();
+            std::fs::remove_dir_all(&folder).unwrap();
+            assert_eq!(
+                err.to_string(),
+                "Tiles are 2x3 pixels but must be at least 3 pixels wide and high"
+            );
+        }
+
+        #[test]
+        fn neighbors_stop_at_edges_unless_periodic() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 4, 3);
+            let neighbors = |model: &SimpleTiled, cell| model.neighbors(cell).collect::<Vec<_>>();
+            assert_eq!(
+                neighbors(&model, 0),
+                [(Direction::Down, 4), (Direction::Right, 1)]
+            );
+            assert_eq!(neighbors(&model, 5).len(), 4);
+            model.periodic = true;
+            assert_eq!(
+                neighbors(&model, 0),
+                [
+                    (Direction::Left, 3),
+                    (Direction::Down, 4),
+                    (Direction::Right, 1),
+                    (Direction::Up, 8)
+                ]
+            );
+            assert_eq!(
+                neighbors(&model, 11),
+                [
+                    (Direction::Left, 10),
+                    (Direction::Down, 3),
+                    (Direction::Right, 8),
+                    (Direction::Up, 7)
+                ]
+            );
+        }
+
+        #[test]
+        fn same_seed_observes_the_same_cells() {
+            let observations = |name: &str| {
+                let mut model = coloring_model(name, 8, 8);
+                model.set_record_events(true);
+                model.run(3, usize::MAX);
+                model
+                    .events()
+                    .iter()
+                    .filter_map(|event| match event {
+                        SolveEvent::Observe { cell, .. } => Some(*cell),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let first = observations("same_seed_a");
+            assert!(first.len() > 1);
+            assert_eq!(first, observations("same_seed_b"));
+        }
+
+        #[test]
+        fn palette_holds_only_used_tiles() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 5, 4);
+            assert!(model.palette().is_err());
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let palette = model.palette().unwrap();
+
+            let mut used: Vec<usize> = model.observed.iter().flatten().copied().collect();
+            used.sort_unstable();
+            used.dedup();
+            assert_eq!(palette.names.len(), used.len());
+            assert_eq!(palette.image.dimensions(), (10 * used.len() as u32, 10));
+            assert_eq!(palette.indices.len(), 4);
+            for (y, row) in palette.indices.iter().enumerate() {
+                assert_eq!(row.len(), 5);
+                for (x, &position) in row.iter().enumerate() {
+                    let t = model.observed[x + y * 5].unwrap();
+                    let position = position.unwrap();
+                    assert_eq!(palette.names[position], model.tile_names[t]);
+                    let tile = imageops::crop_imm(&palette.image, 10 * position as u32, 0, 10, 10);
+                    assert_eq!(tile.to_image(), model.tiles[t].image.to_rgba8());
+                }
+            }
+
+            let path = std::env::temp_dir().join(format!("palette_{}.png", std::process::id()));
+            palette.save(&path).unwrap();
+            let csv = std::fs::read_to_string(path.with_extension("csv")).unwrap();
+            let positions: HashMap<String, usize> =
+                toml::from_str(&std::fs::read_to_string(path.with_extension("toml")).unwrap())
+                    .unwrap();
+            for extension in ["png", "csv", "toml"] {
+                std::fs::remove_file(path.with_extension(extension)).unwrap();
+            }
+            assert_eq!(csv.lines().count(), 4);
+            assert_eq!(positions[&palette.names[1]], 1);
+        }
+
+        #[test]
+        fn failed_run_reports_contradiction() {
+            let mut model = coloring_model("contradiction_stats", 8, 8);
+            let seed = (0..100).find(|&seed| !model.run(seed, usize::MAX)).unwrap();
+            let (x, y) = model.stats().contradiction.unwrap();
+            assert_eq!(model.sums_of_ones[x + y * 8], 0);
+            assert!(model.run(seed + 1, 0));
+            assert_eq!(model.stats().contradiction, None);
+        }
+
+        #[test]
+        fn contradiction_away_from_origin_fails_propagation() {
+            let mut model = coloring_model("far_contradiction", 5, 5);
+            model.clear();
+            // Surround (3, 3) with all three colors
+            for ((x, y), color) in [((2, 3), 0), ((4, 3), 1), ((3, 2), 2)] {
+                let mut allowed = vec![false; 3];
+                allowed[color] = true;
+                let i = model.index(x, y);
+                model.restrict(i, &allowed);
+            }
+            assert!(!model.propagate());
+            assert!(model.sums_of_ones[0] > 0);
+            let (x, y) = model.coords(model.contradiction.unwrap());
+            assert_eq!(model.sums_of_ones[model.index(x, y)], 0);
+            assert!(x.abs_diff(3) + y.abs_diff(3) <= 1);
+        }
+
+        #[test]
+        fn backtracking_rescues_failed_seeds() {
+            let mut model = coloring_model("backtracking", 8, 8);
+            let seed = (0..100).find(|&seed| !model.run(seed, usize::MAX)).unwrap();
+            assert!(!model.run_with_backtracking(seed, 0));
+            assert!(model.run_with_backtracking(seed, 1000));
+            assert!(model.stats().backtracks > 0);
+            assert_eq!(model.stats().contradiction, None);
+            for i in 0..64 {
+                let t = model.observed[i].unwrap();
+                for (_, neighbor) in model.neighbors(i) {
+                    assert_ne!(model.observed[neighbor], Some(t));
+                }
+            }
+        }
+
+        #[test]
+        fn failure_heatmap_marks_contradiction_sites() {
+            let mut model = coloring_model("failure_heatmap", 8, 8);
+            let mut sites = vec![];
+            for trial in 0..20 {
+                if !model.run(derive_seed(7, trial), usize::MAX) {
+                    sites.push(model.stats().contradiction.unwrap());
+                }
+            }
+            assert!(!sites.is_empty());
+            let heatmap = model.failure_heatmap(20, 7);
+            assert_eq!(heatmap.dimensions(), (8, 8));
+            for (x, y, pixel) in heatmap.enumerate_pixels() {
+                let failed = sites.contains(&(x as usize, y as usize));
+                assert_eq!(pixel.0[0] > 0, failed, "({x}, {y})");
+            }
+            assert!(heatmap.pixels().any(|pixel| pixel.0 == [255, 0, 0, 255]));
+        }
+
+        #[test]
+        fn progressive_run_keeps_preview_tiles() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 10, 7);
+            let mut coarse = vec![];
+            let success = (0..100).any(|seed| {
+                model.run_progressive(seed, 3, |preview| {
+                    assert_eq!((preview.width, preview.height), (4, 3));
+                    coarse = preview.observed.clone();
+                })
+            });
+            assert!(success);
+            for (i, t) in coarse.iter().enumerate() {
+                assert_eq!(model.observed[i % 4 * 3 + i / 4 * 3 * 10], *t);
+            }
+            assert!(model.constraints.is_empty());
+        }
+
+        #[test]
+        fn verify_edges_drops_mismatched_adjacencies() {
+            let knot = |verify: bool| {
+                let mut config: Config =
+                    toml::from_str(include_str!("../knot/config.toml")).unwrap();
+                config.set_verify_edges(verify);
+                SimpleTiled::new(config, KNOT, 1, 1, false, Heuristic::Entropy)
+                    .unwrap()
+                    .propagator
+            };
+            assert_eq!(knot(true), knot(false));
+
+            // Red on the left and blue on the right, wrongly marked as symmetric
+            let half = RgbaImage::from_fn(4, 4, |x, _| {
+                if x < 2 {
+                    Rgba([255, 0, 0, 255])
+                } else {
+                    Rgba([0, 0, 255, 255])
+                }
+            });
+            let mut config = Config::new();
+            config
+                .add_tile(Tile::new("half.png", Some("X"), None))
+                .add_neighbor("half", "half")
+                .set_verify_edges(true);
+            let model = image_model(config, &[("half", half)], 1, 1, false).unwrap();
+            assert!(model.propagator[Direction::Left.index()][0].is_empty());
+            assert!(model.propagator[Direction::Right.index()][0].is_empty());
+            assert_eq!(model.propagator[Direction::Down.index()][0], [0]);
+            assert_eq!(model.propagator[Direction::Up.index()][0], [0]);
+        }
+
+        #[test]
+        fn cell_index_and_coords_round_trip() {
+            let model = knot_model(include_str!("../knot/config.toml"), 5, 3);
+            assert_eq!(model.index(0, 0), 0);
+            assert_eq!(model.index(4, 0), 4);
+            assert_eq!(model.index(0, 1), 5);
+            assert_eq!(model.index(3, 2), 13);
+            for i in 0..15 {
+                let (x, y) = model.coords(i);
+                assert!(x < 5 && y < 3);
+                assert_eq!(model.index(x, y), i);
+            }
+        }
+
+        #[test]
+        fn best_orientation_maximizes_score() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 4, 3);
+            assert!(model.best_orientation(|_| 0.).is_err());
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let image = model.render().unwrap();
+            assert_eq!(model.best_orientation(|_| 0.).unwrap(), image);
+
+            let top_left = |image: &RgbaImage| {
+                let corner = imageops::crop_imm(image, 0, 0, 10, 10).to_image();
+                corner.pixels().map(|pixel| pixel.0[0] as f64).sum::<f64>()
+            };
+            let best = model.best_orientation(top_left).unwrap();
+            let mirrored = imageops::flip_horizontal(&image);
+            let all = [
+                imageops::rotate90(&image),
+                imageops::rotate180(&image),
+                imageops::rotate270(&image),
+                imageops::rotate90(&mirrored),
+                imageops::rotate180(&mirrored),
+                imageops::rotate270(&mirrored),
+                mirrored,
+                image,
+            ];
+            assert!(all.iter().any(|candidate| candidate == &best));
+            assert!(all
+                .iter()
+                .all(|candidate| top_left(candidate) <= top_left(&best)));
+            assert_eq!(
+                model
+                    .best_orientation(|image| -(image.width() as f64))
+                    .unwrap()
+                    .width(),
+                30
+            );
+        }
+
+        #[test]
+        fn weight_schedule_follows_progress() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 8, 8);
+            model.set_record_events(true);
+            let empty = model
+                .tile_names
+                .iter()
+                .position(|n| n == "empty 0")
+                .unwrap();
+            let fractions = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+            let seen = fractions.clone();
+            model.set_weight_schedule(move |t, fraction| {
+                seen.borrow_mut().push(fraction);
+                // Only empty tiles until a quarter of the grid is done
+                if fraction < 0.25 && t != empty {
+                    0.
+                } else {
+                    1.
+                }
+            });
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let first = model.events().iter().find_map(|event| match event {
+                SolveEvent::Observe { tile, .. } => Some(*tile),
+                _ => None,
+            });
+            assert_eq!(first, Some(empty));
+            let fractions = fractions.borrow();
+            assert!(fractions.iter().all(|&f| (0. ..1.).contains(&f)));
+            assert!(fractions.iter().any(|&f| f >= 0.25));
+
+            model.clear_weight_schedule();
+            assert!(model.weight_schedule.is_none());
+        }
+
+        #[test]
+        fn model_kind() {
+            let model: Box<dyn Model> =
+                Box::new(knot_model(include_str!("../knot/config.toml"), 2, 2));
+            assert_eq!(model.kind(), ModelKind::SimpleTiled);
+        }
+
+        #[test]
+        fn overlapping_model_keeps_sample_patterns() {
+            use overlapping::OverlappingModel;
+            let black = Rgba([0, 0, 0, 255]);
+            let white = Rgba([255, 255, 255, 255]);
+            let sample =
+                RgbaImage::from_fn(4, 4, |x, y| if (x + y) % 2 == 0 { black } else { white });
+            let mut model = OverlappingModel::new(
+                &DynamicImage::ImageRgba8(sample),
+                2,
+                8,
+                7,
+                5,
+                false,
+                Heuristic::Entropy,
+            )
+            .unwrap();
+            assert_eq!(model.pattern_count(), 2);
+            assert_eq!(model.kind(), ModelKind::Overlapping);
+            assert!(model.run(0, usize::MAX));
+            let image = model.render().unwrap();
+            assert_eq!(image.dimensions(), (7, 5));
+            let first = *image.get_pixel(0, 0);
+            for (x, y, pixel) in image.enumerate_pixels() {
+                assert_eq!(*pixel == first, (x + y) % 2 == 0, "({x}, {y})");
+            }
+        }
+
+        #[test]
+        fn blocks_leave_out_anchors_past_the_edge() {
+            use blocks::BlockModel;
+            let block_model = |width: usize, height: usize, periodic: bool| {
+                let config: Config = toml::from_str(include_str!("../knot/config.toml")).unwrap();
+                BlockModel::new(config, KNOT, 2, width, height, periodic, Heuristic::Entropy)
+            };
+            assert!(block_model(1, 4, false).is_err());
+            let tiles = knot_model(include_str!("../knot/config.toml"), 1, 1);
+            let index = |name: Option<&str>| {
+                let name = name.unwrap();
+                tiles.tile_names.iter().position(|n| n == name).unwrap()
+            };
+
+            for periodic in [false, true] {
+                let mut model = block_model(5, 4, periodic).unwrap();
+                assert_eq!(model.kind(), ModelKind::Blocks);
+                assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+                let anchors = model.model_mut();
+                for y in 0..4 {
+                    for x in 0..5 {
+                        let anchored = periodic || (x + 2 <= 5 && y + 2 <= 4);
+                        let observed = anchors.observed[anchors.index(x, y)].is_some();
+                        assert_eq!(observed, anchored, "({x}, {y})");
+                    }
+                }
+
+                // Including the cells past the last anchors, and across the
+                // edges of the periodic grid
+                let names = model.observed_tiles();
+                for y in 0..4 {
+                    for x in 0..5 {
+                        let t = index(names[x + y * 5]);
+                        for (direction, (nx, ny)) in [
+                            (Direction::Right, (x + 1, y)),
+                            (Direction::Down, (x, y + 1)),
+                        ] {
+                            if !periodic && (nx == 5 || ny == 4) {
+                                continue;
+                            }
+                            let neighbor = index(names[nx % 5 + ny % 4 * 5]);
+                            assert!(tiles.propagator[direction.index()][t].contains(&neighbor));
+                        }
+                    }
+                }
+                let image = model.render().unwrap();
+                assert_eq!(image.dimensions(), (50, 40));
+                for (i, &name) in names.iter().enumerate() {
+                    let (x, y) = ((i % 5) as u32 * 10, (i / 5) as u32 * 10);
+                    let tile = imageops::crop_imm(&image, x, y, 10, 10);
+                    assert_eq!(tile.to_image(), tiles.tiles[index(name)].image.to_rgba8());
+                }
+            }
+        }
+
+        #[test]
+        fn masked_cells_stay_ungenerated() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 4, 4);
+            // A 2x2 pixel image with the top right quarter transparent and the
+            // bottom left one in the key color
+            let mut image = RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 255]));
+            image.put_pixel(1, 0, Rgba([255, 255, 255, 0]));
+            image.put_pixel(0, 1, Rgba([255, 0, 0, 255]));
+            let mask = model.mask_from_image(&DynamicImage::ImageRgba8(image), Some([255, 0, 0]));
+            for y in 0..4 {
+                for x in 0..4 {
+                    assert_eq!(mask[x + y * 4], (x < 2) == (y < 2));
+                }
+            }
+            assert!(model.set_mask(Some(vec![true; 3])).is_err());
+            model.set_mask(Some(mask.clone())).unwrap();
+
+            model.set_background([0, 0, 0, 0]);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let image = model.render().unwrap();
+            for (i, &generated) in mask.iter().enumerate() {
+                assert_eq!(model.observed[i].is_some(), generated);
+                let (x, y) = model.coords(i);
+                let pixel = image.get_pixel(
+                    (x * model.tile_width) as u32,
+                    (y * model.tile_height) as u32,
+                );
+                if !generated {
+                    assert_eq!(pixel.0, [0, 0, 0, 0]);
+                }
+            }
+
+            let palette = model.palette().unwrap();
+            for (i, &generated) in mask.iter().enumerate() {
+                let (x, y) = model.coords(i);
+                assert_eq!(palette.indices[y][x].is_some(), generated);
+            }
+            // Only the generated half of the shared edge is constrained
+            let mut next = knot_model(include_str!("../knot/config.toml"), 4, 4);
+            next.constrain_edge_from(&model, Direction::Left).unwrap();
+            assert_eq!(next.constraints.len(), 2);
+            assert!((0..100).any(|seed| next.run(seed, usize::MAX)));
+        }
+
+        #[test]
+        fn unplaceable_tiles_are_found_before_running() {
+            let model = |width: usize, height: usize, periodic: bool| {
+                let mut config = Config::new();
+                config
+                    .add_tile(Tile::new("r.png", Some("X"), None))
+                    .add_tile(Tile::new("g.png", Some("X"), None))
+                    .add_tile(Tile::new("b.png", Some("X"), None))
+                    .add_neighbor("r", "g")
+                    .add_vertical_neighbor("r", "g")
+                    .set_auto_symmetry_neighbors(false);
+                let tiles = ["r", "g", "b"].map(|name| (name, solid(2, [0, 0, 0, 0])));
+                image_model(config, &tiles, width, height, periodic).unwrap()
+            };
+
+            // `b` has no neighbors and `r` only has some to the right and below
+            let mut open = model(2, 2, false);
+            assert_eq!(open.unplaceable_tiles(), vec!["b 0"]);
+            assert!(open.check_solvable().is_ok());
+            open.set_required_tiles(&["b"]).unwrap();
+            assert!(open.check_solvable().is_err());
+            assert!(model(1, 1, false).unplaceable_tiles().is_empty());
+            let mut periodic = model(2, 2, true);
+            assert_eq!(periodic.unplaceable_tiles(), vec!["r 0", "g 0", "b 0"]);
+            assert!(periodic.check_solvable().is_err());
+
+            // Masked neighbors count as missing, so isolated cells can hold `b`
+            open.set_mask(Some(vec![true, false, false, true])).unwrap();
+            assert!(open.unplaceable_tiles().is_empty());
+            assert!(open.check_solvable().is_ok());
+            assert!((0..100).any(|seed| open.run(seed, usize::MAX)));
+            periodic
+                .set_mask(Some(vec![true, false, false, false]))
+                .unwrap();
+            assert!(periodic.unplaceable_tiles().is_empty());
+            periodic.set_mask(Some(vec![false; 4])).unwrap();
+            assert!(periodic.check_solvable().is_ok());
+        }
+
+        #[test]
+        fn recolored_tiles_share_the_rules_of_their_tile() {
+            let config = include_str!("../knot/config.toml").replacen(
+                r#"{ name="corner.png", symmetry="L" }"#,
+                r#"{ name="corner.png", symmetry="L", recolor=[120] }"#,
+                1,
+            );
+            let plain = knot_model(include_str!("../knot/config.toml"), 4, 4);
+            let mut model = knot_model(
+                &format!("{config}\n[flags]\ncorner = {{ round = true }}"),
+                4,
+                4,
+            );
+            let copies = plain.num_tiles;
+            assert_eq!(model.num_tiles, copies + 4);
+            let corner = model.tile_names.iter().position(|name| name == "corner 0");
+            let corner = corner.unwrap();
+            for i in 0..4 {
+                let (original, copy) = (corner + i, copies + i);
+                assert_eq!(model.tile_names[copy], format!("corner~120 {i}"));
+                assert_eq!(
+                    model.tiles[copy].image,
+                    model.tiles[original].image.huerotate(120)
+                );
+                assert_eq!(model.tile_flags[copy], model.tile_flags[original]);
+                for d in 0..4 {
+                    assert_eq!(model.propagator[d][copy], model.propagator[d][original]);
+                    let without_copies: Vec<usize> = model.propagator[d][original]
+                        .iter()
+                        .copied()
+                        .filter(|&t| t < copies)
+                        .collect();
+                    assert_eq!(without_copies, plain.propagator[d][original]);
+                }
+            }
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+        }
+
+        #[test]
+        fn up_down_neighbors_are_vertical() {
+            let config: Config = toml::from_str(
+                r#"
+                tiles = [ { name="corner.png", symmetry="L" } ]
+                neighbors = [
+                    { left="corner", right="corner 1" },
+                    { up="corner 2", down="corner 3" },
+                    { left="corner 1", right="corner", vertical=true },
+                ]
+                "#,
+            )
+            .unwrap();
+            let rules: Vec<(&str, &str, bool)> = config
+                .neighbors()
+                .iter()
+                .map(|n| (n.left.as_str(), n.right.as_str(), n.vertical))
+                .collect();
+            assert_eq!(
+                rules,
+                vec![
+                    ("corner", "corner 1", false),
+                    ("corner 2", "corner 3", true),
+                    ("corner 1", "corner", true),
+                ]
+            );
+            let written: Vec<String> = config.neighbors().iter().map(ToString::to_string).collect();
+            assert_eq!(
+                written,
+                [
+                    r#"{ left="corner", right="corner 1" }"#,
+                    r#"{ up="corner 2", down="corner 3" }"#,
+                    r#"{ left="corner 1", right="corner", vertical=true }"#,
+                ]
+            );
+        }
+
+        #[test]
+        fn literal_neighbors_without_auto_symmetry() {
+            let tiles = [
+                ("r", solid(2, [255, 0, 0, 255])),
+                ("g", solid(2, [0, 255, 0, 255])),
+            ];
+            let model = |auto: bool, vertical: bool| {
+                let mut config = Config::new();
+                config
+                    .add_tile(Tile::new("r.png", Some("X"), None))
+                    .add_tile(Tile::new("g.png", Some("X"), None))
+                    .add_neighbor("r", "g")
+                    .set_auto_symmetry_neighbors(auto);
+                if vertical {
+                    config.add_vertical_neighbor("r", "g");
+                }
+                image_model(config, &tiles, 2, 2, false)
+            };
+            let (r, g) = (0, 1);
+
+            let literal = model(false, true).unwrap();
+            let allowed = |d: Direction, t: usize| literal.propagator[d.index()][t].clone();
+            assert_eq!(allowed(Direction::Right, r), vec![g]);
+            assert_eq!(allowed(Direction::Left, g), vec![r]);
+            assert_eq!(allowed(Direction::Left, r), vec![]);
+            assert_eq!(allowed(Direction::Down, r), vec![g]);
+            assert_eq!(allowed(Direction::Up, g), vec![r]);
+            assert_eq!(allowed(Direction::Up, r), vec![]);
+
+            let derived = model(true, false).unwrap();
+            assert_eq!(derived.propagator[Direction::Left.index()][r], vec![g]);
+            assert_eq!(derived.propagator[Direction::Up.index()][r], vec![g]);
+            assert!(model(true, true).is_err());
+        }
+
+        #[test]
+        fn agreement_prefers_cells_next_to_collapsed_ones() {
+            // Tiles that fit anywhere, so collapsing a cell leaves every entropy alone
+            let mut model = knot_model(
+                r#"
+                tiles = [ { name="empty.png", symmetry="X" }, { name="cross.png", symmetry="X" } ]
+                neighbors = [
+                    { left="empty", right="empty" },
+                    { left="empty", right="cross" },
+                    { left="cross", right="cross" },
+                ]
+                "#,
+                5,
+                5,
+            );
+            model.clear();
+            for (i, tie_break) in model.tie_breaks.iter_mut().enumerate() {
+                *tie_break = 1e-7 * (25 - i) as f64;
+            }
+            model.ban(12, 1);
+            assert!(model.propagate());
+            assert_eq!(model.next_unobserved_node(), Some(24));
+
+            model.set_heuristic(Heuristic::Agreement);
+            let next = model.next_unobserved_node().unwrap();
+            assert!(model.neighbors(12).any(|(_, neighbor)| neighbor == next));
+        }
+
+        #[cfg(feature = "ora")]
+        #[test]
+        fn ora_has_a_layer_per_used_tile() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 4, 4);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let path = std::env::temp_dir().join(format!("layers_{}.ora", std::process::id()));
+            model.save_ora(&path).unwrap();
+            let archive = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            // The mimetype comes first and uncompressed
+            assert_eq!(&archive[..4], b"PK\x03\x04");
+            assert_eq!(&archive[30..38], b"mimetype");
+            assert_eq!(&archive[38..54], b"image/openraster");
+            let archive = String::from_utf8_lossy(&archive);
+            let used: HashSet<&str> = model
+                .observed
+                .iter()
+                .map(|t| {
+                    let t = t.unwrap();
+                    let base = model
+                        .base_tiles
+                        .iter()
+                        .find(|base| (base.first..base.first + base.cardinality).contains(&t));
+                    base.unwrap().name.as_str()
+                })
+                .collect();
+            for base in &model.base_tiles {
+                let layer = format!("<layer name=\"{}\"", base.name);
+                assert_eq!(archive.contains(&layer), used.contains(base.name.as_str()));
+            }
+            assert!(archive.contains("<layer name=\"background\""));
+        }
+
+        #[test]
+        fn slack_run_crops_a_valid_tiling() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 5, 4);
+            let cross = model
+                .tile_names
+                .iter()
+                .position(|n| n == "cross 0")
+                .unwrap();
+            let mut allowed = vec![false; model.num_tiles];
+            allowed[cross] = true;
+            model.constraints.push((0, allowed));
+
+            assert!((0..100).any(|seed| model.run_with_slack(seed, 2)));
+            assert_eq!(model.observed[0], Some(cross));
+            for i in 0..model.wave.len() {
+                let t = model.observed[i].unwrap();
+                assert_eq!(model.sums_of_ones[i], 1);
+                assert_eq!(model.sums_of_weights[i], model.tiles[t].weight);
+                assert_eq!(model.entropies[i], 0.);
+            }
+            let image = model.render().unwrap();
+            assert_eq!(image.dimensions(), (50, 40));
+            assert_eq!(
+                model
+                    .verify_image(&DynamicImage::ImageRgba8(image))
+                    .unwrap(),
+                vec![]
+            );
+        }
+
+        #[test]
+        fn slack_run_keeps_the_solver_settings() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 5, 4);
+            model.set_heuristic(Heuristic::ScanLine);
+            model.set_deterministic(true);
+            assert!(model.run_with_slack(1, 2));
+            let first = model.observed.clone();
+            assert!(model.run_with_slack(2, 2));
+            assert_eq!(model.observed, first);
+
+            // Nothing fits between two cells, so the larger grid always fails, and
+            // contradictions in its margin still name a cell of the requested one
+            let mut config = Config::new();
+            config
+                .add_tile(Tile::new("r.png", Some("X"), None))
+                .add_tile(Tile::new("g.png", Some("X"), None))
+                .add_neighbor("r", "g")
+                .set_auto_symmetry_neighbors(false);
+            let tiles = ["r", "g"].map(|name| (name, solid(2, [0, 0, 0, 0])));
+            let mut model = image_model(config, &tiles, 3, 2, false).unwrap();
+            assert!(!model.run_with_slack(0, 4));
+            let (x, y) = model.stats().contradiction.unwrap();
+            assert!(x < 3 && y < 2);
+        }
+
+        #[test]
+        fn guide_pulls_cells_towards_its_colors() {
+            let mut model = coloring_model("guide", 6, 6);
+            let red = model.tile_names.iter().position(|n| n == "r 0").unwrap();
+            let mut guide = RgbaImage::from_pixel(2, 1, Rgba([255, 0, 0, 255]));
+            guide.put_pixel(1, 0, Rgba([0, 0, 255, 255]));
+            model.set_guide(&DynamicImage::ImageRgba8(guide), 50.);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+
+            let reds = |columns: std::ops::Range<usize>| {
+                columns
+                    .flat_map(|x| (0..6).map(move |y| x + y * 6))
+                    .filter(|&i| model.observed[i] == Some(red))
+                    .count()
+            };
+            assert!(reds(0..3) > reds(3..6));
+
+            model.set_guide(&DynamicImage::new_rgba8(1, 1), 0.);
+            assert!(model.guide.is_none());
+        }
+
+        #[test]
+        fn non_finite_starting_entropy_is_rejected() {
+            let config = |weight: &str| {
+                let config = format!(
+                    r#"
+                    tiles = [
+                        {{ name="corner.png", symmetry="L" }},
+                        {{ name="line.png", symmetry="I", weight={weight} }},
+                    ]
+                    neighbors = [ {{ left="corner", right="line" }} ]
+                    "#
+                );
+                SimpleTiled::new(
+                    toml::from_str(&config).unwrap(),
+                    KNOT,
+                    2,
+                    2,
+                    false,
+                    Heuristic::Entropy,
+                )
+            };
+            assert!(config("0.5").is_ok());
+            for weight in ["0.0", "-1.0", "nan"] {
+                let err = config(weight).unwrap_err().to_string();
+                assert!(err.contains("check the weights of line"), "{err}");
+            }
+        }
+
+        #[test]
+        fn compact_display_uses_a_character_per_tile() {
+            let mut model = knot_model(
+                r#"
+                tiles = [
+                    { name="corner.png", symmetry="L" },
+                    { name="cross.png", symmetry="X" },
+                    { name="line.png", symmetry="I" },
+                ]
+                neighbors = [
+                    { left="corner", right="cross" },
+                    { left="cross", right="cross" },
+                    { left="cross", right="line" },
+                    { left="line", right="line" },
+                ]
+                "#,
+                3,
+                2,
+            );
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let compact = format!("{model:#}");
+            let mut lines = compact.lines();
+            for y in 0..2 {
+                let row: Vec<char> = lines.next().unwrap().chars().collect();
+                assert_eq!(row.len(), 3);
+                for (x, symbol) in row.into_iter().enumerate() {
+                    let name = &model.tile_names[model.observed[x + y * 3].unwrap()];
+                    // "corner" and "cross" both start with a c
+                    let expected = match name.split(' ').next().unwrap() {
+                        "corner" => 'c',
+                        "cross" => 'A',
+                        _ => 'l',
+                    };
+                    assert_eq!(symbol, expected);
+                }
+            }
+            assert_eq!(lines.next(), Some(""));
+            assert!(lines.all(|line| ["c = corner", "A = cross", "l = line"].contains(&line)));
+            assert_eq!(format!("{model}"), model.format_grid(GridOrder::RowMajor));
+        }
+
+        #[test]
+        fn adjacency_function_builds_the_propagator() {
+            let config: Config = toml::from_str(
+                r#"
+                tiles = [ { name="empty.png", symmetry="X" }, { name="line.png", symmetry="I" } ]
+                neighbors = []
+                "#,
+            )
+            .unwrap();
+            // Lines only continue along their own direction, "line 0" being vertical
+            let model = SimpleTiled::with_adjacency(
+                config,
+                KNOT,
+                4,
+                4,
+                false,
+                Heuristic::Entropy,
+                |a, direction, b| {
+                    let vertical = matches!(direction, Direction::Up | Direction::Down);
+                    match (a, b) {
+                        (1, 1) => vertical,
+                        (2, 2) => !vertical,
+                        (1 | 2, _) | (_, 1 | 2) => false,
+                        _ => true,
+                    }
+                },
+            )
+            .unwrap();
+            assert_eq!(model.tile_names, ["empty 0", "line 0", "line 1"]);
+            assert_eq!(model.propagator[Direction::Down.index()][1], vec![1]);
+            assert_eq!(model.propagator[Direction::Left.index()][1], vec![]);
+            assert_eq!(model.propagator[Direction::Right.index()][2], vec![2]);
+            assert_eq!(model.propagator[Direction::Up.index()][0], vec![0]);
+        }
+
+        #[test]
+        fn tile_caps_limit_coverage() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 10, 10);
+            let empty = model
+                .tile_names
+                .iter()
+                .position(|n| n == "empty 0")
+                .unwrap();
+            let mut uncapped = 0;
+            for seed in 0..20 {
+                if model.run(seed, usize::MAX) {
+                    uncapped = uncapped.max(model.tile_histogram()[empty]);
+                }
+            }
+            assert!(uncapped > 10);
+
+            model.set_tile_caps(&[("empty", 0.05)]).unwrap();
+            let mut solved = 0;
+            for seed in 0..20 {
+                if model.run(seed, usize::MAX) {
+                    solved += 1;
+                    // Propagation may add a few beyond the cap
+                    assert!(model.tile_histogram()[empty] <= 10);
+                }
+            }
+            assert!(solved > 0);
+            assert!(model.set_tile_caps(&[("empty", 1.5)]).is_err());
+            assert!(model.set_tile_caps(&[("lava", 0.1)]).is_err());
+        }
+
+        #[test]
+        fn stepping_matches_a_full_run() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 6, 6);
+            let seed = (0..100).find(|&seed| model.run(seed, usize::MAX)).unwrap();
+            let expected = model.observed.clone();
+
+            model.reset();
+            assert!(model.observed.iter().all(Option::is_none));
+            let mut rng = model.init(seed).unwrap();
+            let mut steps = 0;
+            let result = loop {
+                match model.step(&mut rng) {
+                    StepResult::Progressed => steps += 1,
+                    result => break result,
+                }
+            };
+            assert_eq!(result, StepResult::Finished);
+            assert_eq!(steps, model.stats.observations);
+            assert_eq!(model.observed, expected);
+        }
+
+        #[test]
+        fn animation_ends_on_the_output() {
+            use image::{codecs::gif::GifDecoder, AnimationDecoder};
+
+            let mut model = knot_model(include_str!("../knot/config.toml"), 4, 4);
+            let path = std::env::temp_dir().join(format!("animation_{}.gif", std::process::id()));
+            let seed = (0..100)
+                .find(|&seed| model.save_animation(&path, seed, 2).is_ok())
+                .unwrap();
+            let file = File::open(&path).unwrap();
+            let frames = GifDecoder::new(BufReader::new(file))
+                .unwrap()
+                .into_frames()
+                .collect_frames()
+                .unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let observations = model.stats.observations;
+            assert_eq!(frames.len(), 2 + observations / 2);
+            assert_eq!(frames.last().unwrap().buffer(), &model.render().unwrap());
+
+            model.run(seed, usize::MAX);
+            assert_eq!(model.stats.observations, observations);
+        }
+
+        #[test]
+        fn pinned_tiles_hold_in_every_run() {
+            let mut model = coloring_model("pinned_tiles", 4, 1);
+            model.set_tile(0, 0, "r").unwrap();
+            model.set_tile(2, 0, "r 0").unwrap();
+            assert!(model.set_tile(4, 0, "r").is_err());
+            assert!(model.set_tile(3, 0, "r 1").is_err());
+            assert!(model.set_tile(3, 0, "cyan").is_err());
+            let err = model.set_tile(1, 0, "r").unwrap_err();
+            assert!(err.to_string().starts_with("Pinning 'r' at (1, 0)"));
+
+            let r = model.tile_names.iter().position(|name| name == "r 0");
+            for seed in 0..5 {
+                assert!(model.run(seed, usize::MAX));
+                assert_eq!(model.observed[0], r);
+                assert_eq!(model.observed[2], r);
+            }
+        }
+
+        #[test]
+        fn border_stays_fixed_around_a_generated_interior() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 6, 5);
+            model.set_border("empty").unwrap();
+            let empty = model.tile_names.iter().position(|name| name == "empty 0");
+            let mut interiors = HashSet::new();
+            for seed in 0..10 {
+                assert!(model.run(seed, usize::MAX));
+                let mut interior = vec![];
+                for (i, &t) in model.observed.iter().enumerate() {
+                    let (x, y) = model.coords(i);
+                    if x == 0 || y == 0 || x == 5 || y == 4 {
+                        assert_eq!(t, empty);
+                    } else {
+                        interior.push(t);
+                    }
+                }
+                interiors.insert(interior);
+            }
+            assert!(interiors.len() > 1);
+
+            let mut periodic = knot_model(include_str!("../knot/config.toml"), 6, 5);
+            periodic.periodic = true;
+            assert!(periodic.set_border("empty").is_err());
+        }
+
+        #[test]
+        fn render_matches_the_saved_image() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 3, 3);
+            let unrendered = model.render().unwrap_err();
+            assert_eq!(unrendered.to_string(), "Model is not fully rendered");
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let path = std::env::temp_dir().join(format!("render_{}.png", std::process::id()));
+            model.save(&path).unwrap();
+            let saved = image::open(&path).unwrap().to_rgba8();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(saved, model.render().unwrap());
+        }
+
+        #[test]
+        fn save_as_overrides_the_extension() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 3, 3);
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let path = std::env::temp_dir().join(format!("save_as_{}.dat", std::process::id()));
+            assert!(model.save(&path).is_err());
+            model.save_as(&path, Some(ImageFormat::Png)).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+        }
+
+        #[test]
+        fn direction_tables_agree() {
+            for (i, direction) in Direction::ALL.into_iter().enumerate() {
+                assert_eq!(direction.index(), i);
+                let (dx, dy) = direction.delta();
+                assert_eq!(direction.opposite().delta(), (-dx, -dy));
+                assert_eq!(direction.opposite().opposite(), direction);
+            }
+        }
+
+        #[test]
+        fn edge_blend_fades_across_seams() {
+            let config: Config = toml::from_str(
+                r#"
+                tiles = [
+                    { name="red.png", symmetry="X" },
+                    { name="blue.png", symmetry="X" },
+                ]
+                neighbors = [ { left="red", right="blue" } ]
+                "#,
+            )
+            .unwrap();
+            let tiles = [
+                ("red", solid(4, [255, 0, 0, 255])),
+                ("blue", solid(4, [0, 0, 255, 255])),
+            ];
+            let mut model = image_model(config, &tiles, 2, 1, false).unwrap();
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let plain = model.render().unwrap();
+            model.set_edge_blend(2);
+            let blended = model.render().unwrap();
+
+            let (near, far) = if plain.get_pixel(0, 0)[0] == 255 {
+                (3, 4)
+            } else {
+                (4, 3)
+            };
+            // Only the two columns next to the seam change, symmetrically
+            for x in [0, 1, 6, 7] {
+                assert_eq!(blended.get_pixel(x, 0), plain.get_pixel(x, 0));
+            }
+            let red_side = blended.get_pixel(near, 0);
+            let blue_side = blended.get_pixel(far, 0);
+            assert!(red_side[0] > red_side[2] && red_side[2] > 0);
+            assert_eq!((red_side[0], red_side[2]), (blue_side[2], blue_side[0]));
+            assert!(model
+                .save_streaming(&std::env::temp_dir().join("unused.png"))
+                .is_err());
+        }
+
+        #[test]
+        fn tile_colors_and_minimap() {
+            let mut model = knot_model(include_str!("../knot/config.toml"), 4, 3);
+            let empty = model
+                .tile_names
+                .iter()
+                .position(|name| name == "empty 0")
+                .unwrap();
+            let cross = model
+                .tile_names
+                .iter()
+                .position(|name| name == "cross 0")
+                .unwrap();
+            let uniform = model.tiles[empty].image.to_rgba8().get_pixel(0, 0).0;
+            assert_eq!(model.tile_average_color(empty), uniform);
+            assert_eq!(model.tile_median_color(empty), uniform);
+            assert_ne!(model.tile_average_color(cross), uniform);
+
+            assert!(model.render_minimap().is_err());
+            assert!((0..100).any(|seed| model.run(seed, usize::MAX)));
+            let minimap = model.render_minimap().unwrap();
+            assert_eq!(minimap.dimensions(), (4, 3));
+            let tile = model.observed[1 + 2 * 4].unwrap();
+            assert_eq!(minimap.get_pixel(1, 2).0, model.tile_average_color(tile));
+
+            let red =
+                DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, Rgba([255, 0, 0, 255])));
+            model
+                .reskin(HashMap::from([("empty".to_string(), red)]))
+                .unwrap();
+            assert_eq!(model.tile_average_color(empty), [255, 0, 0, 255]);
+        }
+
+        #[test]
+        fn output_dimensions_fit() {
+            assert_eq!(output_dimensions(40, 30, 10, 10), Ok((400, 300)));
+            assert_eq!(output_dimensions(40, 30, 3, 7), Ok((120, 210)));
+        }
+
+        #[test]
+        fn output_dimensions_overflow() {
+            assert!(output_dimensions(200_000_000, 1, 32, 32).is_err());
+            assert!(output_dimensions(1, 200_000_000, 32, 32).is_err());
+            assert!(output_dimensions(1, 200_000_000, 32, 1).is_ok());
+            assert!(output_dimensions(usize::MAX, 1, 2, 2).is_err());
+        }
+    }
+}
+
+/// Derives the seed for the `index`th image of a batch from a master seed.
+///
+/// The same `(master, index)` pair always yields the same seed, so any image of
+/// a batch can be regenerated from the master seed alone.
+pub fn derive_seed(master: u64, index: u64) -> u64 {
+    // SplitMix64
+    let mut z = master.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// The seed of the `attempt`th try at the `index`th image of a batch.
+///
+/// The first attempt uses [`derive_seed`] of the master seed and index, and each
+/// retry derives from that in turn, so every run started from a master seed
+/// tries the same seeds in the same order.
+pub fn attempt_seed(master: u64, index: u64, attempt: u64) -> u64 {
+    let base = derive_seed(master, index);
+    if attempt == 0 {
+        base
+    } else {
+        derive_seed(base, attempt)
+    }
+}
+
+/// A float in `0..1` from the top 53 bits of the next `u64` of `rng`.
+///
+/// Fixed here rather than left to `rand`'s float sampling, which may change
+/// between versions, so a seed keeps producing the same output.
+fn unit_f64(rng: &mut impl RngCore) -> f64 {
+    (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Picks an index with probability proportional to its weight, using `r` in
+/// `0..1`. Indices without weight are never picked.
+///
+/// Should rounding keep the running sum below `r` times the total, the last
+/// index with weight is returned, and index 0 only if no index has any weight.
+fn random_from_distr(weights: &[f64], r: f64) -> usize {
+    let sum = weights.iter().fold(0., |acc, w| acc + w);
+    let threshold = r * sum;
+    let mut partial_sum = 0.;
+    for (i, weight) in weights.iter().enumerate() {
+        partial_sum += weight;
+        if *weight > 0. && partial_sum >= threshold {
+            return i;
+        }
+    }
+    weights.iter().rposition(|&weight| weight > 0.).unwrap_or(0)
+}
+
+/// Writes `entries` of file names and contents to a zip archive at `path`,
+/// uncompressed and in order, as OpenRaster needs its `mimetype` first.
+#[cfg(feature = "ora")]
+fn write_stored_zip(path: &Path, entries: &[(String, Vec<u8>)]) -> Result<(), Box<dyn Error>> {
+    // 1980-01-01, the earliest date zip can store
+    const DOS_DATE: u16 = (1 << 5) | 1;
+    let size = |len: usize| u32::try_from(len).map_err(|_| "Archive is too large for zip");
+
+    let mut archive = vec![];
+    let mut directory = vec![];
+    for (name, data) in entries {
+        let crc = crc32fast::hash(data);
+        let offset = size(archive.len())?;
+        let header = |signature: u32, central: bool| -> Result<Vec<u8>, Box<dyn Error>> {
+            let mut header = signature.to_le_bytes().to_vec();
+            if central {
+                // Made by version 2.0
+                header.extend(20u16.to_le_bytes());
+            }
+            // Needs version 1.0, no flags, stored, at midnight
+            for field in [10u16, 0, 0, 0, DOS_DATE] {
+                header.extend(field.to_le_bytes());
+            }
+            header.extend(crc.to_le_bytes());
+            header.extend(size(data.len())?.to_le_bytes());
+            header.extend(size(data.len())?.to_le_bytes());
+            header.extend((name.len() as u16).to_le_bytes());
+            // No extra field
+            header.extend(0u16.to_le_bytes());
+            if central {
+                // No comment, disk 0, no attributes
+                header.extend([0; 10]);
+                header.extend(offset.to_le_bytes());
+            }
+            header.extend(name.as_bytes());
+            Ok(header)
+        };
+        archive.extend(header(0x04034b50, false)?);
+        archive.extend(data);
+        directory.extend(header(0x02014b50, true)?);
+    }
+
+    let directory_offset = size(archive.len())?;
+    let count = u16::try_from(entries.len()).map_err(|_| "Too many files for zip")?;
+    archive.extend(&directory);
+    archive.extend(0x06054b50u32.to_le_bytes());
+    // Disk 0 with the directory on it
+    archive.extend([0; 4]);
+    archive.extend(count.to_le_bytes());
+    archive.extend(count.to_le_bytes());
+    archive.extend(size(directory.len())?.to_le_bytes());
+    archive.extend(directory_offset.to_le_bytes());
+    // No comment
+    archive.extend([0; 2]);
+    std::fs::write(path, archive)?;
+    Ok(())
 }
 
 fn name_from_file_name(file_name: &str) -> Result<&str, &str> {
@@ -1,11 +1,20 @@
-use clap::{Parser, Subcommand};
-use model::{Heuristic, Model, SimpleTiled};
+use clap::{ArgEnum, Parser, Subcommand};
+use model::{
+    overlapping::OverlappingModel, EdgeMatching, GridOrder, Heuristic, Model, OutputSymmetry,
+    Recovery, SimpleTiled, TieBreak,
+};
 use std::{
+    collections::BTreeMap,
+    error::Error,
+    ffi::OsStr,
     fs,
     path::{Path, PathBuf},
     process::exit,
+    time::{Duration, Instant},
 };
-use tile_collapse::{model, Config};
+use tile_collapse::{attempt_seed, model, Config};
+
+mod gui;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -16,6 +25,8 @@ struct Args {
 }
 
 #[derive(Subcommand)]
+// Parsed once, so the size of `Cli` doesn't matter
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// Runs this program headless
     Cli {
@@ -34,12 +45,274 @@ enum Commands {
         #[clap(short = 'H', long, default_value = "scan-line", arg_enum)]
         heuristic: Heuristic,
 
+        /// Heuristics to switch to in turn after every --fallback-after failed attempts,
+        /// cycling back to --heuristic after the last one
+        #[clap(long, arg_enum, multiple_values = true, value_name = "HEURISTIC")]
+        heuristic_fallback: Vec<Heuristic>,
+
+        /// How many attempts each heuristic gets before --heuristic-fallback switches
+        #[clap(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..), requires = "heuristic-fallback")]
+        fallback_after: u64,
+
+        /// How mrv picks among the cells with equally few possible tiles
+        #[clap(long, default_value = "random", arg_enum)]
+        tie_break: TieBreak,
+
+        /// Give every cell its heaviest possible tile instead of a random one. With the
+        /// scan-line heuristic every seed gives the same output
+        #[clap(long)]
+        deterministic: bool,
+
+        /// Whether the output image should be tileable
+        #[clap(short, long)]
+        periodic: bool,
+
+        /// Mirror the output as a whole
+        #[clap(short, long, arg_enum)]
+        symmetry: Option<OutputSymmetry>,
+
+        /// Keep opposite edges identical so copies tile when overlapping by one tile
+        #[clap(long, arg_enum)]
+        match_edges: Option<EdgeMatching>,
+
+        /// What to do when a cell is left without any possible tile
+        #[clap(short, long, default_value = "restart", arg_enum)]
+        recovery: Recovery,
+
+        /// Observe up to this many separate cells between propagations (entropy and mrv only)
+        #[clap(long, default_value_t = 1)]
+        batch: usize,
+
+        /// Stop once the mean entropy of undecided cells drops below this, leaving them
+        /// as their first possible tile
+        #[clap(long)]
+        stop_entropy: Option<f64>,
+
+        /// Only observe cells next to collapsed ones, growing the output from a single spot
+        #[clap(long)]
+        frontier: bool,
+
+        /// Leave the cells under transparent pixels of this image ungenerated. The image
+        /// is stretched over the grid and each cell goes by the pixel under its center
+        #[clap(long, value_name = "IMAGE")]
+        mask: Option<PathBuf>,
+
+        /// Also leave the cells under pixels of this color in the mask ungenerated
+        #[clap(long, value_name = "RRGGBB", value_parser = parse_color, requires = "mask")]
+        mask_color: Option<[u8; 3]>,
+
+        /// Steer retries away from the openings of failed attempts
+        #[clap(long)]
+        adaptive: bool,
+
+        /// Blend this many pixels on each side of the seams between tiles
+        #[clap(short, long, default_value_t = 0, conflicts_with = "stream")]
+        blend: u32,
+
+        /// Where to write the image, its extension picks the format, a.png by default.
+        /// With --output-grid-only a .csv or .json path, a.csv by default. With --count
+        /// the master seed and index are added to the name, e.g. a_1234_0.png
+        #[clap(short, long, value_parser = output_path)]
+        output: Option<PathBuf>,
+
+        /// Write only the tile of every cell instead of the image, skipping compositing.
+        /// A .csv output holds the tile indices with their names in a .toml next to it,
+        /// a .json output holds both
+        #[clap(long, conflicts_with_all = &["stream", "blend", "palette", "preview", "csv"])]
+        output_grid_only: bool,
+
+        /// Write the image one row of tiles at a time to save memory (always PNG)
+        #[clap(long)]
+        stream: bool,
+
+        /// Also write the tile IDs from the config's [mapping] table next to each image as CSV
+        #[clap(long)]
+        csv: bool,
+
+        /// Also write a grid of 1s and 0s for this flag from the config's [flags] table
+        /// next to each image, e.g. a.walkable.csv. Can be given multiple times
+        #[clap(long, value_name = "FLAG", multiple_occurrences = true)]
+        flag_csv: Vec<String>,
+
+        /// Also write just the used tiles and an index map into them next to each image,
+        /// e.g. a.palette.png, a.palette.csv and a.palette.toml
+        #[clap(long)]
+        palette: bool,
+
+        /// Also write each image as OpenRaster with every tile on its own layer, e.g. a.ora
+        #[cfg(feature = "ora")]
+        #[clap(long)]
+        ora: bool,
+
+        /// First solve a grid this many times smaller and save it as a preview,
+        /// e.g. a.preview.png, then solve the full grid around it
+        #[clap(long, value_name = "FACTOR", value_parser = clap::value_parser!(u64).range(2..))]
+        preview: Option<u64>,
+
+        /// Write the metrics of every run, failed ones included, to this file as JSON
+        #[clap(long, value_name = "PATH")]
+        stats_json: Option<PathBuf>,
+
+        /// Load the compiled tileset from this file, or compile it there when missing.
+        /// Delete the file after changing the tileset
+        #[clap(long)]
+        cache: Option<PathBuf>,
+
+        /// After this many failed attempts, solve a grid larger by --slack-margin on every
+        /// side and crop it, which can cut off features at the edges
+        #[clap(long, value_name = "ATTEMPTS", conflicts_with_all = &["periodic", "match-edges"])]
+        slack_after: Option<u64>,
+
+        /// How many cells --slack-after adds on every side
+        #[clap(long, default_value_t = 1, requires = "slack-after")]
+        slack_margin: usize,
+
+        /// Undo observations that lead to contradictions, up to this many per attempt,
+        /// instead of starting the attempt over
+        #[clap(long, value_name = "COUNT", conflicts_with = "preview")]
+        max_backtracks: Option<usize>,
+
+        /// Also write a GIF of every image collapsing to this file, numbered like the
+        /// output when generating several
+        #[clap(long, value_name = "PATH", conflicts_with_all = &["preview", "max-backtracks"])]
+        animate: Option<PathBuf>,
+
+        /// How many observations --animate makes between frames
+        #[clap(long, default_value_t = 1, requires = "animate")]
+        frame_every: usize,
+
+        /// Give up on an image after this many failed attempts
+        #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+        max_attempts: Option<u64>,
+
+        /// The master seed every image and retry derives its seed from, as printed by
+        /// an earlier run. Random if not given
+        #[clap(long)]
+        seed: Option<u64>,
+
+        /// Only print warnings, errors and a final `SUCCESS` or `FAILURE` line per image,
+        /// which names the master seed and index to regenerate it with
+        #[clap(short, long)]
+        quiet: bool,
+
+        /// The number of images to generate
+        #[clap(short = 'n', long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        count: u64,
+    },
+    /// Checks that an image is a valid tiling under a tileset's rules
+    Verify {
+        /// The folder including the tile images and a config.toml
+        #[clap(value_parser = is_dir)]
+        input_folder: String,
+
+        /// The image to check
+        #[clap()]
+        image: PathBuf,
+
+        /// Also check adjacency across the wrapped edges
+        #[clap(short, long)]
+        periodic: bool,
+    },
+    /// Writes a labeled sheet of every tile variant
+    Atlas {
+        /// The folder including the tile images and a config.toml
+        #[clap(value_parser = is_dir)]
+        input_folder: String,
+
+        /// Where to write the atlas
+        #[clap(short, long, default_value = "atlas.png")]
+        output: PathBuf,
+    },
+    /// Prints tile weights matching how often each tile appears in an example image
+    Weights {
+        /// The folder including the tile images and a config.toml
+        #[clap(value_parser = is_dir)]
+        input_folder: String,
+
+        /// The example to take the tile frequencies from
+        #[clap()]
+        image: PathBuf,
+    },
+    /// Generates every tileset at the same seed and size into one labeled sheet
+    Compare {
+        /// The folders including the tile images and a config.toml
+        #[clap(value_parser = is_dir, min_values = 2, required = true)]
+        input_folders: Vec<String>,
+
+        /// The width of each output in tiles
+        #[clap(long, default_value_t = 10)]
+        width: usize,
+        /// The height of each output in tiles
+        #[clap(long, default_value_t = 10)]
+        height: usize,
+
+        /// The heuristic used to generate the next tile
+        #[clap(short = 'H', long, default_value = "scan-line", arg_enum)]
+        heuristic: Heuristic,
+
+        /// Whether the outputs should be tileable
+        #[clap(short, long)]
+        periodic: bool,
+
+        /// The master seed shared by every tileset, random if not given
+        #[clap(short, long)]
+        seed: Option<u64>,
+
+        /// Give up on a tileset after this many failed attempts
+        #[clap(long, default_value_t = 100)]
+        max_attempts: usize,
+
+        /// Where to write the sheet
+        #[clap(short, long, default_value = "compare.png")]
+        output: PathBuf,
+    },
+    /// Generates an image whose every NxN window appears in a sample image
+    Overlapping {
+        /// The sample image, which wraps around at its edges
+        #[clap()]
+        sample: PathBuf,
+
+        /// The width of the output image in pixels
+        #[clap()]
+        width: usize,
+        /// The height of the output image in pixels
+        #[clap()]
+        height: usize,
+
+        /// The size of the patterns taken from the sample
+        #[clap(short = 'N', long, default_value_t = 3)]
+        n: usize,
+
+        /// How many reflections and rotations of each pattern to add, from 1 to 8
+        #[clap(short, long, default_value_t = 8, value_parser = clap::value_parser!(u64).range(1..=8))]
+        symmetry: u64,
+
+        /// The heuristic used to generate the next pixel
+        #[clap(short = 'H', long, default_value = "entropy", arg_enum)]
+        heuristic: Heuristic,
+
         /// Whether the output image should be tileable
         #[clap(short, long)]
         periodic: bool,
+
+        /// The master seed every retry derives its seed from, random if not given
+        #[clap(long)]
+        seed: Option<u64>,
+
+        /// Give up after this many failed attempts
+        #[clap(long, default_value_t = 100)]
+        max_attempts: u64,
+
+        /// Where to write the image
+        #[clap(short, long, default_value = "a.png", value_parser = output_path)]
+        output: PathBuf,
+    },
+    /// Runs this program in a gui served to the browser [default subcommand]
+    Gui {
+        /// The local port to serve the gui on, 0 picks a free one
+        #[clap(long, default_value_t = gui::DEFAULT_PORT)]
+        port: u16,
     },
-    /// Runs this program in a gui [default subcommand]
-    Gui,
 }
 
 fn is_dir(s: &str) -> Result<String, String> {
@@ -62,47 +335,626 @@ fn is_dir(s: &str) -> Result<String, String> {
     }
 }
 
+fn output_path(s: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+    match image::ImageFormat::from_path(&path) {
+        Ok(format) if format.can_write() => {}
+        _ if is_grid_path(&path) => {}
+        _ => Err(format!("{s} doesn't end in a supported image extension"))?,
+    }
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && !parent.is_dir() => {
+            Err(format!("{} isn't a directory", parent.to_string_lossy()))
+        }
+        _ => Ok(path),
+    }
+}
+
+/// Whether `path` is for the tile grid written by `--output-grid-only`.
+fn is_grid_path(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(OsStr::to_str),
+        Some("csv" | "json")
+    )
+}
+
+/// Writes the tile index of every cell of `model`, `width` cells per row, as
+/// CSV with the index of each tile name in a TOML file next to it, or as JSON
+/// holding the names and the rows. Cells left out by a mask are empty or null.
+fn save_grid(model: &SimpleTiled, width: usize, path: &Path) -> Result<(), Box<dyn Error>> {
+    let tiles = model.observed_tiles(GridOrder::RowMajor);
+    let rows = tiles.chunks(width.max(1));
+    if path.extension().and_then(OsStr::to_str) == Some("json") {
+        let names: Vec<String> = model.tile_names().iter().map(|n| json_string(n)).collect();
+        let rows: Vec<String> = rows
+            .map(|row| {
+                let cells: Vec<String> = row
+                    .iter()
+                    .map(|t| t.map_or("null".to_string(), |t| t.to_string()))
+                    .collect();
+                format!("    [{}]", cells.join(", "))
+            })
+            .collect();
+        let json = format!(
+            "{{\n  \"tiles\": [{}],\n  \"grid\": [\n{}\n  ]\n}}\n",
+            names.join(", "),
+            rows.join(",\n")
+        );
+        fs::write(path, json)?;
+    } else {
+        let mut csv = String::new();
+        for row in rows {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|t| t.map_or(String::new(), |t| t.to_string()))
+                .collect();
+            csv += &cells.join(",");
+            csv += "\n";
+        }
+        fs::write(path, csv)?;
+        let indices: BTreeMap<&str, usize> = model
+            .tile_names()
+            .iter()
+            .enumerate()
+            .map(|(t, name)| (name.as_str(), t))
+            .collect();
+        fs::write(path.with_extension("toml"), toml::to_string(&indices)?)?;
+    }
+    Ok(())
+}
+
+fn parse_color(s: &str) -> Result<[u8; 3], String> {
+    let hex = s.trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("{s} isn't a RRGGBB color"));
+    }
+    let mut color = [0; 3];
+    for (channel, value) in color.iter_mut().enumerate() {
+        *value = u8::from_str_radix(&hex[2 * channel..2 * channel + 2], 16)
+            .map_err(|_| format!("{s} isn't a RRGGBB color"))?;
+    }
+    Ok(color)
+}
+
+fn load_config(input_folder: &str) -> Config {
+    read_config(input_folder).unwrap_or_else(|err| {
+        println!("{err}");
+        exit(1)
+    })
+}
+
+/// Prints the warnings `model` gathered since the last call.
+fn print_warnings(model: &mut SimpleTiled) {
+    for warning in model.take_warnings() {
+        eprintln!("WARNING: {warning}");
+    }
+}
+
+/// Reads the config.toml in `input_folder` and expands its tile globs.
+fn read_config(input_folder: &str) -> Result<Config, Box<dyn Error>> {
+    let mut config = PathBuf::from(input_folder);
+    config.push("config.toml");
+
+    let content = std::fs::read_to_string(&config)
+        .map_err(|err| format!("Couldn't read {}: {err}", config.to_string_lossy()))?;
+    let mut config: Config = toml::from_str(&content)
+        .map_err(|err| format!("config.toml does not have the correct format: {err}"))?;
+    config.expand_tile_globs(input_folder)?;
+
+    Ok(config)
+}
+
+/// The metrics of the run `model` just finished as a JSON object.
+fn run_json(
+    model: &SimpleTiled,
+    seed: u64,
+    (width, height): (usize, usize),
+    heuristic: &Heuristic,
+    success: bool,
+    elapsed: Duration,
+) -> String {
+    let stats = model.stats();
+    let contradiction = match stats.contradiction {
+        Some((x, y)) => format!("{{ \"x\": {x}, \"y\": {y} }}"),
+        None => "null".to_string(),
+    };
+    let mut histogram = BTreeMap::new();
+    for t in model
+        .observed_tiles(GridOrder::RowMajor)
+        .into_iter()
+        .flatten()
+    {
+        *histogram.entry(&model.tile_names()[t]).or_insert(0) += 1;
+    }
+    let histogram: Vec<String> = histogram
+        .into_iter()
+        .map(|(name, count)| format!("{}: {count}", json_string(name)))
+        .collect();
+    let heuristic = heuristic
+        .to_possible_value()
+        .map_or(String::new(), |value| value.get_name().to_string());
+    format!(
+        "  {{ \"seed\": {seed}, \"width\": {width}, \"height\": {height}, \"heuristic\": {}, \
+         \"success\": {success}, \"observations\": {}, \"contradictions\": {}, \
+         \"contradiction\": {contradiction}, \"peak_stack_depth\": {}, \"elapsed_ms\": {:.3}, \
+         \"tiles\": {{ {} }} }}",
+        json_string(&heuristic),
+        stats.observations,
+        stats.contradictions,
+        stats.peak_stack_depth,
+        elapsed.as_secs_f64() * 1000.,
+        histogram.join(", ")
+    )
+}
+
+/// `s` as a quoted JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            c if (c as u32) < 0x20 => quoted += &format!("\\u{:04x}", c as u32),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
 fn main() {
     let args = Args::parse();
 
-    match args.command.unwrap_or(Commands::Gui) {
+    match args.command.unwrap_or(Commands::Gui {
+        port: gui::DEFAULT_PORT,
+    }) {
         Commands::Cli {
             input_folder,
             width,
             height,
             heuristic,
+            heuristic_fallback,
+            fallback_after,
+            tie_break,
+            deterministic,
             periodic,
+            symmetry,
+            match_edges,
+            recovery,
+            batch,
+            stop_entropy,
+            frontier,
+            mask,
+            mask_color,
+            adaptive,
+            blend,
+            output,
+            output_grid_only,
+            stream,
+            csv,
+            flag_csv,
+            palette,
+            #[cfg(feature = "ora")]
+            ora,
+            preview,
+            stats_json,
+            cache,
+            slack_after,
+            slack_margin,
+            max_backtracks,
+            animate,
+            frame_every,
+            max_attempts,
+            seed,
+            quiet,
+            count,
         } => {
+            let output = output
+                .unwrap_or_else(|| PathBuf::from(if output_grid_only { "a.csv" } else { "a.png" }));
+            if output_grid_only != is_grid_path(&output) {
+                println!(
+                    "{} needs {}",
+                    output.to_string_lossy(),
+                    if output_grid_only {
+                        "a .csv or .json extension with --output-grid-only"
+                    } else {
+                        "an image extension, or --output-grid-only for .csv and .json"
+                    }
+                );
+                exit(1)
+            }
             let dir = Path::new(&input_folder);
-            let mut config = PathBuf::from(&input_folder);
-            config.push("config.toml");
 
-            let content = std::fs::read_to_string(config).unwrap();
-            let config: Config = toml::from_str(&content).unwrap_or_else(|err| {
-                println!("config.toml does not have the correct format: {err}");
+            let tiled_model = match &cache {
+                Some(cache) if cache.exists() => {
+                    SimpleTiled::from_compiled(cache, width, height, periodic, heuristic.clone())
+                }
+                _ => {
+                    let config = load_config(&input_folder);
+                    SimpleTiled::new(
+                        config,
+                        dir.to_str().unwrap(),
+                        width,
+                        height,
+                        periodic,
+                        heuristic.clone(),
+                    )
+                    .and_then(|model| {
+                        if let Some(cache) = &cache {
+                            model.compile(cache)?;
+                        }
+                        Ok(model)
+                    })
+                }
+            };
+
+            if let Ok(mut tiled_model) = tiled_model.map_err(|err| println!("{err}")) {
+                print_warnings(&mut tiled_model);
+                tiled_model.set_output_symmetry(symmetry);
+                tiled_model.set_edge_matching(match_edges);
+                tiled_model.set_recovery(recovery);
+                tiled_model.set_adaptive_retries(adaptive);
+                tiled_model.set_tie_break(tie_break);
+                tiled_model.set_deterministic(deterministic);
+                tiled_model.set_frontier_only(frontier);
+                tiled_model.set_batch_observe(batch);
+                tiled_model.set_stop_entropy(stop_entropy);
+                tiled_model.set_edge_blend(blend);
+                tiled_model.set_progress(!quiet);
+                if let Some(mask) = &mask {
+                    let image = image::open(mask).unwrap_or_else(|err| {
+                        println!("Couldn't open {}: {err}", mask.to_string_lossy());
+                        exit(1)
+                    });
+                    let mask = tiled_model.mask_from_image(&image, mask_color);
+                    if let Err(err) = tiled_model.set_mask(Some(mask)) {
+                        println!("{err}");
+                        exit(1)
+                    }
+                }
+                if let Err(err) = tiled_model.check_solvable() {
+                    println!("{err}");
+                    exit(1)
+                }
+                for name in tiled_model.unplaceable_tiles() {
+                    eprintln!("WARNING: tile {name} can never appear in a {width}x{height} grid");
+                }
+                let master_seed = seed.unwrap_or_else(rand::random);
+                if !quiet {
+                    println!("Master seed: {master_seed}");
+                }
+                let report = |res: Result<(), Box<dyn Error>>| {
+                    if !quiet || res.is_err() {
+                        println!("{:?}", res);
+                    }
+                };
+                let heuristics: Vec<Heuristic> = std::iter::once(heuristic)
+                    .chain(heuristic_fallback)
+                    .collect();
+                let heuristic_name = |heuristic: &Heuristic| {
+                    heuristic
+                        .to_possible_value()
+                        .map_or(String::new(), |value| value.get_name().to_string())
+                };
+                let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+                let extension = output.extension().unwrap_or_default().to_string_lossy();
+                let mut runs = vec![];
+                let mut failed = false;
+                for index in 0..count {
+                    let mut seed = attempt_seed(master_seed, index, 0);
+                    let mut attempt = 0;
+                    let started = Instant::now();
+                    let mut current = &heuristics[0];
+                    let success = loop {
+                        let turn = (attempt / fallback_after) as usize % heuristics.len();
+                        if current != &heuristics[turn] {
+                            current = &heuristics[turn];
+                            if !quiet {
+                                println!("Switching to {}", heuristic_name(current));
+                            }
+                        }
+                        tiled_model.set_heuristic(current.clone());
+                        let start = Instant::now();
+                        let success = match preview {
+                            _ if slack_after.is_some_and(|after| attempt >= after) => {
+                                tiled_model.run_with_slack(seed, slack_margin)
+                            }
+                            _ if animate.is_some() => {
+                                let animate = animate.as_ref().unwrap();
+                                let path = if count == 1 {
+                                    animate.clone()
+                                } else {
+                                    let stem = animate.file_stem().unwrap_or_default();
+                                    animate.with_file_name(format!(
+                                        "{}_{index}.gif",
+                                        stem.to_string_lossy()
+                                    ))
+                                };
+                                let res = tiled_model.save_animation(&path, seed, frame_every);
+                                if !quiet {
+                                    println!("Animation: {:?}", res);
+                                }
+                                res.is_ok()
+                            }
+                            Some(factor) => {
+                                tiled_model.run_progressive(seed, factor as usize, |coarse| {
+                                    let path = if count == 1 {
+                                        output.with_extension(format!("preview.{extension}"))
+                                    } else {
+                                        output.with_file_name(format!(
+                                            "{stem}_{index}.preview.{extension}"
+                                        ))
+                                    };
+                                    let res = coarse.save(&path);
+                                    if !quiet || res.is_err() {
+                                        println!("Preview: {:?}", res);
+                                    }
+                                })
+                            }
+                            None => match max_backtracks {
+                                Some(limit) => tiled_model.run_with_backtracking(seed, limit),
+                                None => tiled_model.run(seed, usize::MAX),
+                            },
+                        };
+                        if let Some(path) = &stats_json {
+                            runs.push(run_json(
+                                &tiled_model,
+                                seed,
+                                (width, height),
+                                current,
+                                success,
+                                start.elapsed(),
+                            ));
+                            if let Err(err) =
+                                fs::write(path, format!("[\n{}\n]\n", runs.join(",\n")))
+                            {
+                                println!("Couldn't write {}: {err}", path.to_string_lossy());
+                            }
+                        }
+                        print_warnings(&mut tiled_model);
+                        attempt += 1;
+                        if success || max_attempts == Some(attempt) {
+                            break success;
+                        }
+                        seed = attempt_seed(master_seed, index, attempt);
+                    };
+                    let mut status = format!(
+                        "master={master_seed} index={index} seed={seed} attempts={attempt} elapsed={:.3}s",
+                        started.elapsed().as_secs_f64()
+                    );
+                    if heuristics.len() > 1 {
+                        status += &format!(" heuristic={}", heuristic_name(current));
+                    }
+                    if !success {
+                        println!("FAILURE {status}");
+                        failed = true;
+                        continue;
+                    }
+                    let output = if count == 1 {
+                        output.clone()
+                    } else {
+                        output.with_file_name(format!("{stem}_{master_seed}_{index}.{extension}"))
+                    };
+                    if !quiet {
+                        println!("Image {index} used seed {seed}");
+                    }
+                    let res = if output_grid_only {
+                        save_grid(&tiled_model, width, &output)
+                    } else if stream {
+                        tiled_model.save_streaming(&output)
+                    } else {
+                        tiled_model.save(&output)
+                    };
+                    let saved = res.is_ok();
+                    report(res);
+                    if palette {
+                        let path = output.with_extension("palette.png");
+                        report(
+                            tiled_model
+                                .palette()
+                                .and_then(|palette| palette.save(&path)),
+                        );
+                    }
+                    #[cfg(feature = "ora")]
+                    if ora {
+                        report(tiled_model.save_ora(&output.with_extension("ora")));
+                    }
+                    if csv {
+                        report(tiled_model.save_mapped_csv(&output.with_extension("csv")));
+                    }
+                    for flag in &flag_csv {
+                        let path = output.with_extension(format!("{flag}.csv"));
+                        report(tiled_model.save_flag_csv(&path, flag));
+                    }
+                    if saved {
+                        println!("SUCCESS {status} output={}", output.to_string_lossy());
+                    } else {
+                        println!("FAILURE {status}");
+                        failed = true;
+                    }
+                }
+                if failed {
+                    exit(1);
+                }
+            }
+        }
+        Commands::Verify {
+            input_folder,
+            image,
+            periodic,
+        } => {
+            let config = load_config(&input_folder);
+            let image = image::open(&image).unwrap_or_else(|err| {
+                println!("Couldn't open {}: {err}", image.to_string_lossy());
                 exit(1)
             });
-
-            //println!("Hello, world! config={:?}", config);
-
-            if let Ok(mut tiled_model) = SimpleTiled::new(
-                config,
-                dir.to_str().unwrap(),
+            let violations =
+                SimpleTiled::new(config, &input_folder, 1, 1, periodic, Heuristic::ScanLine)
+                    .and_then(|mut model| {
+                        print_warnings(&mut model);
+                        model.verify_image(&image)
+                    })
+                    .unwrap_or_else(|err| {
+                        println!("{err}");
+                        exit(1)
+                    });
+            if violations.is_empty() {
+                println!("Image is a valid tiling");
+            } else {
+                for violation in &violations {
+                    println!("{violation}");
+                }
+                exit(1);
+            }
+        }
+        Commands::Atlas {
+            input_folder,
+            output,
+        } => {
+            let config = load_config(&input_folder);
+            let res = SimpleTiled::new(config, &input_folder, 1, 1, false, Heuristic::ScanLine)
+                .and_then(|mut model| {
+                    print_warnings(&mut model);
+                    Ok(model.tile_atlas().save(&output)?)
+                });
+            println!("{:?}", res);
+        }
+        Commands::Weights {
+            input_folder,
+            image,
+        } => {
+            let config = load_config(&input_folder);
+            let image = image::open(&image).unwrap_or_else(|err| {
+                println!("Couldn't open {}: {err}", image.to_string_lossy());
+                exit(1)
+            });
+            let block = SimpleTiled::new(
+                config.clone(),
+                &input_folder,
+                1,
+                1,
+                false,
+                Heuristic::ScanLine,
+            )
+            .and_then(|mut model| {
+                print_warnings(&mut model);
+                model.example_weights(&image)
+            })
+            .and_then(|weights| config.weight_block(&weights))
+            .unwrap_or_else(|err| {
+                println!("{err}");
+                exit(1)
+            });
+            print!("{block}");
+        }
+        Commands::Compare {
+            input_folders,
+            width,
+            height,
+            heuristic,
+            periodic,
+            seed,
+            max_attempts,
+            output,
+        } => {
+            let seed = seed.unwrap_or_else(rand::random);
+            println!("Seed: {seed}");
+            let mut models = vec![];
+            for folder in &input_folders {
+                let config = load_config(folder);
+                let mut tiled_model =
+                    SimpleTiled::new(config, folder, width, height, periodic, heuristic.clone())
+                        .unwrap_or_else(|err| {
+                            println!("{folder}: {err}");
+                            exit(1)
+                        });
+                print_warnings(&mut tiled_model);
+                // Every folder tries the seeds of image 0 so a rerun reproduces the sheet
+                let attempt = (0..max_attempts as u64).find(|&attempt| {
+                    let success = tiled_model.run(attempt_seed(seed, 0, attempt), usize::MAX);
+                    print_warnings(&mut tiled_model);
+                    success
+                });
+                match attempt {
+                    Some(0) => {}
+                    Some(attempt) => println!(
+                        "{folder} needed {} attempts, last seed {}",
+                        attempt + 1,
+                        attempt_seed(seed, 0, attempt)
+                    ),
+                    None => {
+                        println!("{folder} failed after {max_attempts} attempts");
+                        exit(1)
+                    }
+                }
+                let label = Path::new(folder)
+                    .file_name()
+                    .map_or(folder.clone(), |name| name.to_string_lossy().into_owned());
+                models.push((label, tiled_model));
+            }
+            let entries: Vec<(&str, &SimpleTiled)> = models
+                .iter()
+                .map(|(label, model)| (label.as_str(), model))
+                .collect();
+            let res =
+                SimpleTiled::comparison_sheet(&entries).and_then(|sheet| Ok(sheet.save(&output)?));
+            println!("{:?}", res);
+        }
+        Commands::Overlapping {
+            sample,
+            width,
+            height,
+            n,
+            symmetry,
+            heuristic,
+            periodic,
+            seed,
+            max_attempts,
+            output,
+        } => {
+            let image = image::open(&sample).unwrap_or_else(|err| {
+                println!("Couldn't open {}: {err}", sample.to_string_lossy());
+                exit(1)
+            });
+            let mut model = OverlappingModel::new(
+                &image,
+                n,
+                symmetry as usize,
                 width,
                 height,
                 periodic,
                 heuristic,
             )
-            .map_err(|err| println!("{err}"))
-            {
-                //println!("{tiled_model}");
-                while !tiled_model.run(rand::random(), usize::MAX) {}
-                //println!("{tiled_model}");
-                let res = tiled_model.save(Path::new("a.png"));
-                println!("{:?}", res);
+            .unwrap_or_else(|err| {
+                println!("{err}");
+                exit(1)
+            });
+            model.model_mut().set_progress(true);
+            let master_seed = seed.unwrap_or_else(rand::random);
+            println!(
+                "Master seed: {master_seed}, {} patterns",
+                model.pattern_count()
+            );
+            let Some(seed) = (0..max_attempts)
+                .map(|attempt| attempt_seed(master_seed, 0, attempt))
+                .find(|&seed| {
+                    let success = model.run(seed, usize::MAX);
+                    print_warnings(model.model_mut());
+                    success
+                })
+            else {
+                println!("FAILURE after {max_attempts} attempts");
+                exit(1)
+            };
+            println!("Used seed {seed}");
+            println!("{:?}", model.save(&output));
+        }
+        Commands::Gui { port } => {
+            if let Err(err) = gui::serve(port) {
+                println!("Couldn't serve the gui: {err}");
+                exit(1)
             }
         }
-        Commands::Gui => todo!("Gui"),
     }
 }
 